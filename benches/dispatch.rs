@@ -0,0 +1,52 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use server_in_rust::request::Request;
+use server_in_rust::server::Server;
+use server_in_rust::handler::Handler;
+use server_in_rust::service::{BoxedService, HttpStatus, Payload, ToPayload};
+
+const ITERATIONS: u32 = 200_000;
+
+fn bench(name: &str, mut f: impl FnMut()) -> Duration {
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let per_call = start.elapsed() / ITERATIONS;
+    println!("{name:<24} {per_call:?}");
+    per_call
+}
+
+fn ping() -> HttpStatus {
+    HttpStatus::Success
+}
+
+fn double(x: u64) -> (HttpStatus, u64) {
+    (HttpStatus::Success, x * 2)
+}
+
+// Compares handlers called directly, through their boxed service and through
+// `Server::handle_request`, which adds routing and building the payload.
+fn main() {
+    let mut server = Server::new();
+    server.get("/ping", ping).get("/double", double);
+
+    let ping_request = Request::get("/ping", vec![]);
+    let double_request = Request::get("/double", 21u64.to_payload());
+
+    let boxed_ping = BoxedService::from_handler(Handler::new(ping));
+    let boxed_double = BoxedService::from_handler(Handler::new(double));
+    let double_payload = 21u64.to_payload();
+
+    bench("direct zero-arg", || { black_box(ping()); });
+    bench("boxed zero-arg", || { black_box(boxed_ping.handle(Payload::from_bytes(&[]))); });
+    bench("served zero-arg", || { black_box(server.handle_request(ping_request.clone())); });
+    bench("direct scalar-arg", || { black_box(double(black_box(21))); });
+    bench("boxed scalar-arg", || { black_box(boxed_double.handle(Payload::from_bytes(&double_payload))); });
+    bench("served scalar-arg", || { black_box(server.handle_request(double_request.clone())); });
+}
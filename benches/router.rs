@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use server_in_rust::request::Path;
+use server_in_rust::router::Router;
+
+const ITERATIONS: u32 = 200_000;
+
+fn bench(name: &str, mut f: impl FnMut()) -> Duration {
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let per_call = start.elapsed() / ITERATIONS;
+    println!("{name:<24} {per_call:?}");
+    per_call
+}
+
+// Compares static route lookups in the router with the exact `HashMap`
+// lookups it replaced.
+fn main() {
+    let patterns: Vec<Path> = (0..1000).map(|i| Path::from(format!("/api/v1/resource{i}/items"))).collect();
+
+    let map: HashMap<Path, usize> = patterns.iter().cloned().zip(0..).collect();
+    let mut router = Router::new();
+    for (i, pattern) in patterns.iter().enumerate() {
+        router.insert(pattern.clone(), i);
+    }
+
+    let path = Path::from("/api/v1/resource500/items");
+    bench("hash map static", || { black_box(map.get(black_box(&path))); });
+    bench("router static", || { black_box(router.find(black_box(&path), false)); });
+}
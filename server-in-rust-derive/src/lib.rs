@@ -0,0 +1,311 @@
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+enum Fields {
+    Named(Vec<String>),
+    Unnamed(usize),
+    Unit,
+}
+
+struct Variant {
+    name: String,
+    tag: u64,
+    fields: Fields,
+}
+
+enum Data {
+    Struct(Fields),
+    // The tag type, `u8` unless the enum has a `#[repr]`.
+    Enum(String, Vec<Variant>),
+}
+
+struct Input {
+    name: String,
+    data: Data,
+}
+
+/// Structs decode their fields in order. Enums first decode a tag, the
+/// variant's discriminant, as their `#[repr]` type (`u8` by default), then
+/// the fields of that variant.
+#[proc_macro_derive(FromPayload)]
+pub fn derive_from_payload(input: TokenStream) -> TokenStream {
+    match parse(input, "FromPayload") {
+        Ok(input) => expand(&input).parse().unwrap(),
+        Err(msg) => format!("compile_error!({msg:?});").parse().unwrap(),
+    }
+}
+
+/// Writes the layout `FromPayload` reads: the tag of an enum, then the
+/// fields in order.
+#[proc_macro_derive(ToPayload)]
+pub fn derive_to_payload(input: TokenStream) -> TokenStream {
+    match parse(input, "ToPayload") {
+        Ok(input) => expand_to_payload(&input).parse().unwrap(),
+        Err(msg) => format!("compile_error!({msg:?});").parse().unwrap(),
+    }
+}
+
+fn expand(input: &Input) -> String {
+    let body = match &input.data {
+        Data::Struct(fields) => format!("::std::result::Result::Ok({})", construct(&input.name, fields)),
+        Data::Enum(repr, variants) => {
+            let arms: Vec<String> = variants
+                .iter()
+                .map(|v| {
+                    let path = format!("{}::{}", input.name, v.name);
+                    format!("{} => ::std::result::Result::Ok({}),", v.tag, construct(&path, &v.fields))
+                })
+                .collect();
+            format!(
+                "match <{repr} as ::server_in_rust::service::FromPayload>::from(payload)? {{
+                    {arms}
+                    tag => ::std::result::Result::Err(::server_in_rust::service::PayloadError::InvalidDiscriminant(tag as u32)),
+                }}",
+                arms = arms.join("\n"),
+            )
+        }
+    };
+
+    format!(
+        "impl<'__p> ::server_in_rust::service::FromPayload<'__p> for {name} {{
+            fn from(payload: &mut ::server_in_rust::service::Payload<'__p>) -> ::std::result::Result<Self, ::server_in_rust::service::PayloadError> {{
+                {body}
+            }}
+        }}",
+        name = input.name,
+    )
+}
+
+fn expand_to_payload(input: &Input) -> String {
+    let arms: Vec<String> = match &input.data {
+        Data::Struct(fields) => vec![encode(&input.name, fields, None)],
+        Data::Enum(repr, variants) => variants
+            .iter()
+            .map(|v| encode(&format!("{}::{}", input.name, v.name), &v.fields, Some(format!("&{}{repr}", v.tag))))
+            .collect(),
+    };
+
+    format!(
+        "impl ::server_in_rust::service::ToPayload for {name} {{
+            fn to_payload(&self) -> ::std::vec::Vec<u8> {{
+                let mut __bytes = ::std::vec::Vec::new();
+                match self {{
+                    {arms}
+                }}
+                __bytes
+            }}
+        }}",
+        name = input.name,
+        arms = arms.join("\n"),
+    )
+}
+
+fn encode(path: &str, fields: &Fields, tag: Option<String>) -> String {
+    let (pattern, bindings) = match fields {
+        Fields::Named(names) => (format!("{path} {{ {} }}", names.join(", ")), names.clone()),
+        Fields::Unnamed(count) => {
+            let bindings: Vec<String> = (0..*count).map(|i| format!("__f{i}")).collect();
+            (format!("{path}({})", bindings.join(", ")), bindings)
+        }
+        Fields::Unit => (path.to_string(), vec![]),
+    };
+
+    // The bindings are references already, as `self` is matched by reference.
+    let writes: String = tag
+        .into_iter()
+        .chain(bindings)
+        .map(|value| format!("__bytes.extend(::server_in_rust::service::ToPayload::to_payload({value}));"))
+        .collect();
+    format!("{pattern} => {{ {writes} }}")
+}
+
+fn construct(path: &str, fields: &Fields) -> String {
+    let extract = "::server_in_rust::service::FromPayload::from(payload)?";
+    match fields {
+        Fields::Named(names) => {
+            let fields: Vec<String> = names.iter().map(|n| format!("{n}: {extract}")).collect();
+            format!("{path} {{ {} }}", fields.join(", "))
+        }
+        Fields::Unnamed(count) => {
+            let fields = vec![extract; *count];
+            format!("{path}({})", fields.join(", "))
+        }
+        Fields::Unit => path.to_string(),
+    }
+}
+
+fn parse(input: TokenStream, derive: &str) -> Result<Input, String> {
+    let mut tokens = input.into_iter().peekable();
+
+    let repr = skip_attrs_and_vis(&mut tokens);
+    let is_enum = match tokens.next() {
+        Some(TokenTree::Ident(ident)) if ident.to_string() == "struct" => false,
+        Some(TokenTree::Ident(ident)) if ident.to_string() == "enum" => true,
+        _ => return Err(format!("{derive} can only be derived for structs and enums")),
+    };
+
+    let name = match tokens.next() {
+        Some(TokenTree::Ident(ident)) => ident.to_string(),
+        _ => return Err("expected type name".into()),
+    };
+
+    if let Some(TokenTree::Punct(p)) = tokens.peek() {
+        if p.as_char() == '<' {
+            return Err(format!("{derive} cannot be derived for generic types"));
+        }
+    }
+
+    let data = match is_enum {
+        false => Data::Struct(parse_fields(tokens.next())?),
+        true => {
+            let repr = repr.unwrap_or_else(|| "u8".to_string());
+            if !["u8", "u16", "u32"].contains(&repr.as_str()) {
+                return Err(format!("{derive} enums need a u8, u16 or u32 repr"));
+            }
+            match tokens.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => Data::Enum(repr, parse_variants(g.stream())?),
+                _ => return Err("expected enum body".into()),
+            }
+        }
+    };
+
+    Ok(Input { name, data })
+}
+
+fn parse_fields(token: Option<TokenTree>) -> Result<Fields, String> {
+    match token {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => {
+            let names = split_fields(g.stream())
+                .into_iter()
+                .map(|field| {
+                    let mut field = field.into_iter().peekable();
+                    skip_attrs_and_vis(&mut field);
+                    match field.next() {
+                        Some(TokenTree::Ident(ident)) => Ok(ident.to_string()),
+                        _ => Err("expected field name".to_string()),
+                    }
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Fields::Named(names))
+        }
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+            Ok(Fields::Unnamed(split_fields(g.stream()).len()))
+        }
+        Some(TokenTree::Punct(p)) if p.as_char() == ';' => Ok(Fields::Unit),
+        _ => Err("unsupported struct definition".into()),
+    }
+}
+
+// Variants without an explicit discriminant take the previous one plus one,
+// starting at zero, as in Rust itself.
+fn parse_variants(stream: TokenStream) -> Result<Vec<Variant>, String> {
+    let mut next_tag = 0;
+
+    split_fields(stream)
+        .into_iter()
+        .map(|variant| {
+            let mut tokens = variant.into_iter().peekable();
+            skip_attrs_and_vis(&mut tokens);
+
+            let name = match tokens.next() {
+                Some(TokenTree::Ident(ident)) => ident.to_string(),
+                _ => return Err("expected variant name".to_string()),
+            };
+            let fields = match tokens.peek() {
+                Some(TokenTree::Group(_)) => parse_fields(tokens.next())?,
+                _ => Fields::Unit,
+            };
+            let tag = match tokens.next() {
+                Some(TokenTree::Punct(p)) if p.as_char() == '=' => {
+                    let literal: String = tokens.map(|t| t.to_string()).collect();
+                    parse_int(&literal).ok_or(format!("unsupported discriminant `{literal}`"))?
+                }
+                None => next_tag,
+                _ => return Err("unsupported variant definition".to_string()),
+            };
+
+            next_tag = tag + 1;
+            Ok(Variant { name, tag, fields })
+        })
+        .collect()
+}
+
+// Decimal or `0x` hex literals, with an optional type suffix like `u8`.
+fn parse_int(literal: &str) -> Option<u64> {
+    let literal = literal.replace('_', "");
+    let suffix = ["u8", "u16", "u32", "u64", "usize", "i8", "i16", "i32", "i64", "isize"]
+        .into_iter()
+        .find(|suffix| literal.ends_with(suffix))
+        .unwrap_or("");
+    let digits = &literal[..literal.len() - suffix.len()];
+
+    match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => digits.parse().ok(),
+    }
+}
+
+// Skips attributes and a visibility, returning the type of a `#[repr(..)]`
+// among the attributes if there is one.
+fn skip_attrs_and_vis(tokens: &mut std::iter::Peekable<impl Iterator<Item = TokenTree>>) -> Option<String> {
+    let mut repr = None;
+    loop {
+        match tokens.peek() {
+            Some(TokenTree::Punct(p)) if p.as_char() == '#' => {
+                tokens.next();
+                if let Some(TokenTree::Group(attr)) = tokens.next() {
+                    repr = repr.or_else(|| repr_type(attr.stream()));
+                }
+            }
+            Some(TokenTree::Ident(ident)) if ident.to_string() == "pub" => {
+                tokens.next();
+                if let Some(TokenTree::Group(g)) = tokens.peek() {
+                    if g.delimiter() == Delimiter::Parenthesis {
+                        tokens.next();
+                    }
+                }
+            }
+            _ => break,
+        }
+    }
+    repr
+}
+
+fn repr_type(attr: TokenStream) -> Option<String> {
+    let mut tokens = attr.into_iter();
+    match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Ident(ident)), Some(TokenTree::Group(args))) if ident.to_string() == "repr" => args
+            .stream()
+            .into_iter()
+            .map(|t| t.to_string())
+            .find(|t| t.starts_with('u') || t.starts_with('i')),
+        _ => None,
+    }
+}
+
+// Splits the body of a struct on top level commas, so that the commas in
+// types like `HashMap<K, V>` do not start a new field.
+fn split_fields(stream: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut fields = vec![];
+    let mut current = vec![];
+    let mut depth = 0;
+
+    for token in stream {
+        if let TokenTree::Punct(p) = &token {
+            match p.as_char() {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                ',' if depth == 0 => {
+                    fields.push(std::mem::take(&mut current));
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        current.push(token);
+    }
+
+    if !current.is_empty() {
+        fields.push(current);
+    }
+    fields
+}
@@ -1,19 +1,33 @@
+use std::any::TypeId;
 use std::marker::PhantomData;
+use std::sync::{Mutex, PoisonError};
 
+use crate::service::{FromRequest, Payload, PayloadError};
+
+/// A function whose arguments `Args` can each be extracted from a request.
+/// `call` extracts them in order and invokes the function, `check` only
+/// extracts them.
 pub trait Factory<Args, Res> {
-    fn call(&self, args: Args) -> Res;
+    fn call(&self, payload: &mut Payload<'_>) -> Result<Res, PayloadError>;
+
+    fn check(&self, payload: &mut Payload<'_>) -> Result<(), PayloadError>;
+
+    /// The `FromRequest::dependency` of each argument that has one.
+    fn dependencies(&self) -> Vec<(TypeId, &'static str)> {
+        vec![]
+    }
 }
 
 pub struct Handler<F, A, R> {
     f: F,
-    _t: PhantomData<(A, R)>,
+    _t: PhantomData<fn(A) -> R>,
 }
 
 impl<F, A, R> Clone for Handler<F, A, R> 
     where F: Clone,
 {
     fn clone(&self) -> Self {
-        Self { f: self.f.clone(), _t: self._t.clone() }
+        Self { f: self.f.clone(), _t: self._t }
     }
 }
 
@@ -24,12 +38,20 @@ where
     pub fn new(f: F) -> Self {
         Handler {
             f,
-            _t: PhantomData::default(),
+            _t: PhantomData,
         }
     }
 
-    pub fn call(&self, args: A) -> R {
-        self.f.call(args)
+    pub fn call(&self, payload: &mut Payload<'_>) -> Result<R, PayloadError> {
+        self.f.call(payload)
+    }
+
+    pub fn check(&self, payload: &mut Payload<'_>) -> Result<(), PayloadError> {
+        self.f.check(payload)
+    }
+
+    pub fn dependencies(&self) -> Vec<(TypeId, &'static str)> {
+        self.f.dependencies()
     }
 }
 
@@ -37,29 +59,105 @@ impl<T, Res> Factory<(), Res> for T
 where
     T: Fn() -> Res,
 {
-    fn call(&self, _args: ()) -> Res {
-        (self)()
+    fn call(&self, payload: &mut Payload<'_>) -> Result<Res, PayloadError> {
+        payload.finish()?;
+        Ok((self)())
+    }
+
+    fn check(&self, payload: &mut Payload<'_>) -> Result<(), PayloadError> {
+        payload.finish()
+    }
+}
+
+/// Wraps an `FnMut` handler so it can mutate what it captures. Calls are
+/// serialized by a lock held while the handler runs, so concurrent requests
+/// to it wait for each other. Arguments are extracted before taking the lock.
+/// A call that panics leaves the captured state as it was at the panic, and
+/// later calls go on with it.
+pub struct Stateful<F>(Mutex<F>);
+
+impl<F> Stateful<F> {
+    pub fn new(f: F) -> Self {
+        Stateful(Mutex::new(f))
+    }
+}
+
+impl<T, Res> Factory<(), Res> for Stateful<T>
+where
+    T: FnMut() -> Res,
+{
+    fn call(&self, payload: &mut Payload<'_>) -> Result<Res, PayloadError> {
+        payload.finish()?;
+        Ok((self.0.lock().unwrap_or_else(PoisonError::into_inner))())
+    }
+
+    fn check(&self, payload: &mut Payload<'_>) -> Result<(), PayloadError> {
+        payload.finish()
     }
 }
 
-macro_rules! factory_tuple {( $(($arg: ident, $n: tt)),+ ) => {
+// The first `Fn` bound pins down the argument types, the second lets the
+// extracted values borrow from the payload for any lifetime.
+macro_rules! factory_tuple {( $($arg: ident),+ ) => {
         impl<T, $($arg,)+ Res> Factory<($($arg,)+), Res> for T
-            where T: Fn($($arg,)+) -> Res
+            where
+                $($arg: FromRequest,)+
+                T: Fn($($arg,)+) -> Res + for<'a> Fn($(<$arg as FromRequest>::Output<'a>,)+) -> Res,
         {
-            fn call(&self, args: ($($arg,)+)) -> Res {
-                (self)($(args.$n,)+)
+            #[allow(non_snake_case)]
+            fn call(&self, payload: &mut Payload<'_>) -> Result<Res, PayloadError> {
+                $(let $arg = $arg::from_request(payload)?;)+
+                payload.finish()?;
+                Ok((self)($($arg,)+))
+            }
+
+            fn check(&self, payload: &mut Payload<'_>) -> Result<(), PayloadError> {
+                $($arg::from_request(payload)?;)+
+                payload.finish()
+            }
+
+            fn dependencies(&self) -> Vec<(TypeId, &'static str)> {
+                [$($arg::dependency(),)+].into_iter().flatten().collect()
+            }
+        }
+
+        impl<T, $($arg,)+ Res> Factory<($($arg,)+), Res> for Stateful<T>
+            where
+                $($arg: FromRequest,)+
+                T: FnMut($($arg,)+) -> Res + for<'a> FnMut($(<$arg as FromRequest>::Output<'a>,)+) -> Res,
+        {
+            #[allow(non_snake_case)]
+            fn call(&self, payload: &mut Payload<'_>) -> Result<Res, PayloadError> {
+                $(let $arg = $arg::from_request(payload)?;)+
+                payload.finish()?;
+                Ok((self.0.lock().unwrap_or_else(PoisonError::into_inner))($($arg,)+))
+            }
+
+            fn check(&self, payload: &mut Payload<'_>) -> Result<(), PayloadError> {
+                $($arg::from_request(payload)?;)+
+                payload.finish()
+            }
+
+            fn dependencies(&self) -> Vec<(TypeId, &'static str)> {
+                [$($arg::dependency(),)+].into_iter().flatten().collect()
             }
         }
     };
 }
 
-factory_tuple!((Arg0, 0));
-factory_tuple!((Arg0, 0), (Arg1, 1));
-factory_tuple!((Arg0, 0), (Arg1, 1), (Arg2, 2));
-factory_tuple!((Arg0, 0), (Arg1, 1), (Arg2, 2), (Arg3, 3));
-factory_tuple!((Arg0, 0), (Arg1, 1), (Arg2, 2), (Arg3, 3), (Arg4, 4));
-factory_tuple!((Arg0, 0), (Arg1, 1), (Arg2, 2), (Arg3, 3), (Arg4, 4), (Arg5, 5));
-factory_tuple!((Arg0, 0), (Arg1, 1), (Arg2, 2), (Arg3, 3), (Arg4, 4), (Arg5, 5), (Arg6, 6));
-factory_tuple!((Arg0, 0), (Arg1, 1), (Arg2, 2), (Arg3, 3), (Arg4, 4), (Arg5, 5), (Arg6, 6), (Arg7, 7));
-factory_tuple!((Arg0, 0), (Arg1, 1), (Arg2, 2), (Arg3, 3), (Arg4, 4), (Arg5, 5), (Arg6, 6), (Arg7, 7), (Arg8, 8));
-factory_tuple!((Arg0, 0), (Arg1, 1), (Arg2, 2), (Arg3, 3), (Arg4, 4), (Arg5, 5), (Arg6, 6), (Arg7, 7), (Arg8, 8), (Arg9, 9));
+factory_tuple!(Arg0);
+factory_tuple!(Arg0, Arg1);
+factory_tuple!(Arg0, Arg1, Arg2);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3, Arg4);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3, Arg4, Arg5);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13, Arg14);
+factory_tuple!(Arg0, Arg1, Arg2, Arg3, Arg4, Arg5, Arg6, Arg7, Arg8, Arg9, Arg10, Arg11, Arg12, Arg13, Arg14, Arg15);
@@ -1,13 +1,23 @@
+extern crate self as server_in_rust;
+
+mod executor;
 pub mod handler;
+pub mod metrics;
+pub mod middleware;
+pub mod payload;
+mod pool;
+pub mod router;
 pub mod service;
 pub mod server;
-mod request;
+pub mod request;
+pub mod testing;
 
 #[cfg(test)]
 mod tests {
-    use std::io::Write;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
 
-    use crate::{server::*, service::HttpStatus, request::Request};
+    use crate::{middleware::Middleware, request, server::*, service::{ByteOrder, FromPayload, HttpStatus, Inject, PayloadError, Response, StreamBody, ServiceResponse, State, ToPayload}, request::{Deadline, FullPath, MatchedPath, Method, Params, Path, Query, Raw, Request, RequestId, RequestType}, testing::TestClient};
 
     fn success() -> HttpStatus {
         HttpStatus::Success
@@ -39,8 +49,8 @@ mod tests {
             let req2 = Request::get("/book", 10usize.to_le_bytes().into());
             let req3 = {
                 let mut buf = vec![];
-                buf.write(&20usize.to_le_bytes()).unwrap();
-                buf.write(&1.2f32.to_le_bytes()).unwrap();
+                buf.write_all(&20usize.to_le_bytes()).unwrap();
+                buf.write_all(&1.2f32.to_le_bytes()).unwrap();
                 Request::post("/bill", buf)
             };
             let err_request1 = Request::get("/404", vec![]);
@@ -54,4 +64,1526 @@ mod tests {
             println!("{res:?}");
         }
     }
+
+    fn update_book(book_no: usize, price: f32) -> impl Into<HttpStatus> {
+        println!("update book no.{book_no} price: {price}");
+
+        HttpStatus::Success
+    }
+
+    #[test]
+    fn put_delete_patch() {
+        let mut server = Server::new();
+
+        server
+            .put("/book", update_book)
+            .delete("/book", query_book)
+            .patch("/book", update_book);
+
+        let payload = {
+            let mut buf = vec![];
+            buf.write_all(&20usize.to_le_bytes()).unwrap();
+            buf.write_all(&1.2f32.to_le_bytes()).unwrap();
+            buf
+        };
+
+        let res = server.handle_request(Request::put("/book", payload.clone()));
+        assert_eq!(res.0, HttpStatus::Success);
+        let res = server.handle_request(Request::delete("/book", 10usize.to_le_bytes().into()));
+        assert_eq!(res.0, HttpStatus::Success);
+        let res = server.handle_request(Request::patch("/book", payload));
+        assert_eq!(res.0, HttpStatus::Success);
+        let res = server.handle_request(Request::get("/book", vec![]));
+        assert_eq!(res.0, HttpStatus::MethodNotAllowed);
+    }
+
+    #[test]
+    fn route_with_method() {
+        let mut server = Server::new();
+
+        server
+            .route(RequestType::Get, "/", success)
+            .route(RequestType::Delete, "/book", query_book);
+
+        let res = server.handle_request(Request::get("/", vec![]));
+        assert_eq!(res.0, HttpStatus::Success);
+        let res = server.handle_request(Request::delete("/book", 10usize.to_le_bytes().into()));
+        assert_eq!(res.0, HttpStatus::Success);
+        let res = server.handle_request(Request::post("/", vec![]));
+        assert_eq!(res.0, HttpStatus::MethodNotAllowed);
+    }
+
+    fn sum(xs: Vec<i32>) -> impl Into<HttpStatus> {
+        println!("sum: {}", xs.iter().sum::<i32>());
+
+        HttpStatus::Success
+    }
+
+    #[test]
+    fn vec_argument() {
+        let mut server = Server::new();
+        server.post("/sum", sum);
+
+        let mut buf = vec![];
+        buf.write_all(&3u32.to_ne_bytes()).unwrap();
+        for x in [1i32, 2, 3] {
+            buf.write_all(&x.to_ne_bytes()).unwrap();
+        }
+
+        let res = server.handle_request(Request::post("/sum", buf));
+        assert_eq!(res.0, HttpStatus::Success);
+
+        let res = server.handle_request(Request::post("/sum", 3u32.to_ne_bytes().into()));
+        assert_eq!(res.0, HttpStatus::BadRequest);
+    }
+
+    #[test]
+    fn big_endian_server() {
+        let mut server = Server::new().with_byte_order(ByteOrder::BigEndian);
+        server.post("/sum", sum);
+
+        let mut buf = vec![];
+        buf.write_all(&2u32.to_be_bytes()).unwrap();
+        buf.write_all(&1i32.to_be_bytes()).unwrap();
+        buf.write_all(&2i32.to_be_bytes()).unwrap();
+
+        let res = server.handle_request(Request::post("/sum", buf));
+        assert_eq!(res.0, HttpStatus::Success);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn report(
+        a: u8, b: u16, c: u32, d: u64, e: i8, f: i16,
+        g: i32, h: i64, i: f32, j: f64, k: usize, l: isize,
+    ) -> impl Into<HttpStatus> {
+        assert_eq!((a, b, c, d, e, f), (1, 2, 3, 4, -5, -6));
+        assert_eq!((g, h, i, j, k, l), (-7, -8, 9.5, 10.5, 11, -12));
+
+        HttpStatus::Success
+    }
+
+    #[test]
+    fn twelve_arguments() {
+        let mut server = Server::new();
+        server.post("/report", report);
+
+        let mut buf = vec![];
+        buf.write_all(&1u8.to_ne_bytes()).unwrap();
+        buf.write_all(&2u16.to_ne_bytes()).unwrap();
+        buf.write_all(&3u32.to_ne_bytes()).unwrap();
+        buf.write_all(&4u64.to_ne_bytes()).unwrap();
+        buf.write_all(&(-5i8).to_ne_bytes()).unwrap();
+        buf.write_all(&(-6i16).to_ne_bytes()).unwrap();
+        buf.write_all(&(-7i32).to_ne_bytes()).unwrap();
+        buf.write_all(&(-8i64).to_ne_bytes()).unwrap();
+        buf.write_all(&9.5f32.to_ne_bytes()).unwrap();
+        buf.write_all(&10.5f64.to_ne_bytes()).unwrap();
+        buf.write_all(&11usize.to_ne_bytes()).unwrap();
+        buf.write_all(&(-12isize).to_ne_bytes()).unwrap();
+
+        let res = server.handle_request(Request::post("/report", buf));
+        assert_eq!(res.0, HttpStatus::Success);
+    }
+
+    #[derive(FromPayload, ToPayload)]
+    struct Order {
+        id: u32,
+        name: String,
+        items: Vec<u16>,
+    }
+
+    #[derive(FromPayload, ToPayload)]
+    struct Point(i32, i32);
+
+    #[derive(Debug, PartialEq, FromPayload, ToPayload)]
+    enum Shape {
+        Circle { radius: f32 },
+        Rect(u16, u16),
+    }
+
+    #[derive(Debug, PartialEq, FromPayload, ToPayload)]
+    #[repr(u32)]
+    enum Command {
+        Stop = 0x10,
+        Move(i8),
+    }
+
+    fn create(order: Order, point: Point) -> impl Into<HttpStatus> {
+        assert_eq!(order.id, 7);
+        assert_eq!(order.name, "desk");
+        assert_eq!(order.items, vec![1, 2]);
+        assert_eq!((point.0, point.1), (-1, 1));
+
+        HttpStatus::Success
+    }
+
+    #[test]
+    fn derived_argument() {
+        let mut server = Server::new();
+        server.post("/order", create);
+
+        let mut buf = vec![];
+        buf.write_all(&7u32.to_ne_bytes()).unwrap();
+        buf.write_all(&4u32.to_ne_bytes()).unwrap();
+        buf.write_all(b"desk").unwrap();
+        buf.write_all(&2u32.to_ne_bytes()).unwrap();
+        buf.write_all(&1u16.to_ne_bytes()).unwrap();
+        buf.write_all(&2u16.to_ne_bytes()).unwrap();
+        buf.write_all(&(-1i32).to_ne_bytes()).unwrap();
+        buf.write_all(&1i32.to_ne_bytes()).unwrap();
+
+        let order = Order { id: 7, name: "desk".to_string(), items: vec![1, 2] };
+        assert_eq!((order, Point(-1, 1)).to_payload(), buf);
+
+        let res = server.handle_request(Request::post("/order", buf));
+        assert_eq!(res.0, HttpStatus::Success);
+    }
+
+    // Only decoded, so its fields need not implement `ToPayload`.
+    #[derive(FromPayload)]
+    struct Ticket {
+        seat: Seat,
+    }
+
+    struct Seat(u8);
+
+    impl<'a> FromPayload<'a> for Seat {
+        fn from(payload: &mut crate::service::Payload<'a>) -> Result<Self, PayloadError> {
+            <u8 as FromPayload>::from(payload).map(Seat)
+        }
+    }
+
+    #[test]
+    fn decode_only_derive() {
+        let mut server = Server::new();
+        server.post("/ticket", |ticket: Ticket| (HttpStatus::Success, ticket.seat.0));
+
+        assert_eq!(TestClient::new(&server).post("/ticket").field(12u8).send(), (HttpStatus::Success, vec![12]));
+    }
+
+    fn checked_div(a: i32, b: i32) -> Result<HttpStatus, String> {
+        let q = a.checked_div(b).ok_or("division by zero")?;
+        println!("{a} / {b} = {q}");
+
+        Ok(HttpStatus::Success)
+    }
+
+    #[test]
+    fn result_return() {
+        let mut server = Server::new();
+        server.post("/div", checked_div);
+
+        let mut buf = vec![];
+        buf.write_all(&6i32.to_ne_bytes()).unwrap();
+        buf.write_all(&3i32.to_ne_bytes()).unwrap();
+        let res = server.handle_request(Request::post("/div", buf));
+        assert_eq!(res.0, HttpStatus::Success);
+
+        let mut buf = vec![];
+        buf.write_all(&6i32.to_ne_bytes()).unwrap();
+        buf.write_all(&0i32.to_ne_bytes()).unwrap();
+        let res = server.handle_request(Request::post("/div", buf));
+        assert_eq!(res.0, HttpStatus::Failed);
+    }
+
+    fn book_title(book_no: usize) -> (HttpStatus, String) {
+        (HttpStatus::Success, format!("book no.{book_no}"))
+    }
+
+    #[test]
+    fn response_body() {
+        let mut server = Server::new();
+        server.get("/book/title", book_title);
+
+        let res = server.handle_request(Request::get("/book/title", 3usize.to_payload()));
+        assert_eq!(res, (HttpStatus::Success, "book no.3".to_string().to_payload()));
+    }
+
+    fn book_by_id(params: Params) -> (HttpStatus, String) {
+        (HttpStatus::Success, format!("param {}", params.get("id").unwrap()))
+    }
+
+    fn book_chapter(params: Params, page: u32) -> (HttpStatus, String) {
+        let (id, chapter) = (params.get("id").unwrap(), params.get("chapter").unwrap());
+        (HttpStatus::Success, format!("{id}/{chapter}/{page}"))
+    }
+
+    fn latest_book() -> (HttpStatus, String) {
+        (HttpStatus::Success, "static".to_string())
+    }
+
+    #[test]
+    fn path_params() {
+        let mut server = Server::new();
+        server
+            .get("/book/:id", book_by_id)
+            .get("/book/latest", latest_book)
+            .get("/book/:id/:chapter", book_chapter);
+
+        let res = server.handle_request(Request::get("/book/42", vec![]));
+        assert_eq!(res, (HttpStatus::Success, "param 42".to_string().to_payload()));
+
+        let res = server.handle_request(Request::get("/book/latest", vec![]));
+        assert_eq!(res, (HttpStatus::Success, "static".to_string().to_payload()));
+
+        let res = server.handle_request(Request::get("/book/7/intro", 3u32.to_payload()));
+        assert_eq!(res, (HttpStatus::Success, "7/intro/3".to_string().to_payload()));
+
+        let res = server.handle_request(Request::get("/book/7/intro/more", vec![]));
+        assert_eq!(res.0, HttpStatus::NotFound);
+    }
+
+    fn search_books(query: Query) -> (HttpStatus, String) {
+        (HttpStatus::Success, format!("{} {}", query.get("author").unwrap(), query.get("year").unwrap()))
+    }
+
+    #[test]
+    fn query_argument() {
+        let mut server = Server::new();
+        server.get("/book", search_books);
+
+        let res = server.handle_request(Request::get("/book?author=rust&year=2023", vec![]));
+        assert_eq!(res, (HttpStatus::Success, "rust 2023".to_string().to_payload()));
+    }
+
+    fn file_tree(params: Params) -> (HttpStatus, String) {
+        (HttpStatus::Success, format!("tree {}", params.get("rest").unwrap()))
+    }
+
+    fn named_file(params: Params) -> (HttpStatus, String) {
+        (HttpStatus::Success, format!("file {}", params.get("name").unwrap()))
+    }
+
+    #[test]
+    fn wildcard_route() {
+        let mut server = Server::new();
+        server
+            .get("/files/*rest", file_tree)
+            .get("/files/readme", latest_book);
+
+        let res = server.handle_request(Request::get("/files/a/b/c", vec![]));
+        assert_eq!(res, (HttpStatus::Success, "tree a/b/c".to_string().to_payload()));
+
+        let res = server.handle_request(Request::get("/files/a", vec![]));
+        assert_eq!(res, (HttpStatus::Success, "tree a".to_string().to_payload()));
+
+        let res = server.handle_request(Request::get("/files/readme", vec![]));
+        assert_eq!(res, (HttpStatus::Success, "static".to_string().to_payload()));
+
+        let res = server.handle_request(Request::get("/files", vec![]));
+        assert_eq!(res.0, HttpStatus::NotFound);
+    }
+
+    #[test]
+    fn ambiguous_route_panics() {
+        let register = || {
+            let mut server = Server::new();
+            server.get("/files/*rest", file_tree).get("/files/:name", named_file);
+        };
+        let err = std::panic::catch_unwind(register).unwrap_err();
+        let expected = RouteError::Ambiguous { method: RequestType::Get, path: "/files/:name".into(), existing: "/files/*rest".into() };
+        assert_eq!(err.downcast_ref::<String>(), Some(&expected.to_string()));
+
+        // The static segment after the param decides between these two.
+        let mut server = Server::new();
+        server.get("/files/*rest", file_tree).get("/files/:name/meta", named_file);
+        assert_eq!(server.handle_request(Request::get("/files/a/meta", vec![])), (HttpStatus::Success, "file a".to_string().to_payload()));
+        assert_eq!(server.handle_request(Request::get("/files/a/b", vec![])), (HttpStatus::Success, "tree a/b".to_string().to_payload()));
+    }
+
+    #[test]
+    fn mount_sub_server() {
+        let mut api = Server::new();
+        api.get("/book", query_book).post("/bill", post_bill);
+
+        let mut server = Server::new();
+        server.get("/", success);
+        server.mount("/api/v1/", api).unwrap();
+
+        let res = server.handle_request(Request::get("/api/v1/book", 1usize.to_payload()));
+        assert_eq!(res.0, HttpStatus::Success);
+        let res = server.handle_request(Request::get("/book", 1usize.to_payload()));
+        assert_eq!(res.0, HttpStatus::NotFound);
+
+        let mut conflicting = Server::new();
+        conflicting.get("/book", success).get("/new", success);
+        let err = server.mount("/api/v1", conflicting).err().unwrap();
+        assert_eq!(err, vec![RouteError::Conflict { method: RequestType::Get, path: "/api/v1/book".into() }]);
+
+        let res = server.handle_request(Request::get("/api/v1/new", vec![]));
+        assert_eq!(res.0, HttpStatus::NotFound);
+    }
+
+    #[test]
+    fn route_group() {
+        let mut server = Server::new();
+        server
+            .group("/admin/", |g| {
+                g.get("/", success).get("book", query_book).post("/bill", post_bill);
+            })
+            .get("/", success);
+
+        let res = server.handle_request(Request::get("/admin", vec![]));
+        assert_eq!(res.0, HttpStatus::Success);
+        let res = server.handle_request(Request::get("/admin/book", 1usize.to_payload()));
+        assert_eq!(res.0, HttpStatus::Success);
+        let res = server.handle_request(Request::get("/book", 1usize.to_payload()));
+        assert_eq!(res.0, HttpStatus::NotFound);
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        let mut server = Server::new();
+        server.get("/book/:id", book_by_id).delete("/book/:id", book_by_id);
+
+        let res = server.handle_request(Request::post("/book/1", vec![]));
+        let allowed = vec!["get".to_string(), "delete".to_string()];
+        assert_eq!(res, (HttpStatus::MethodNotAllowed, allowed.to_payload()));
+        assert_eq!(server.allowed_methods(&"/book/1".into()), vec![RequestType::Get, RequestType::Delete]);
+
+        let res = server.handle_request(Request::post("/bill", vec![]));
+        assert_eq!(res.0, HttpStatus::NotFound);
+    }
+
+    fn branded_not_found(method: RequestType, path: Path) -> (HttpStatus, String) {
+        (HttpStatus::NotFound, format!("no {method} route for {path}"))
+    }
+
+    #[test]
+    fn fallback_handler() {
+        let mut server = Server::new();
+        server.get("/", success).fallback(branded_not_found);
+
+        let res = server.handle_request(Request::get("/", vec![]));
+        assert_eq!(res.0, HttpStatus::Success);
+
+        let res = server.handle_request(Request::post("/missing", vec![]));
+        let path: Path = "/missing".into();
+        let expected = format!("no post route for {path}");
+        assert_eq!(res, (HttpStatus::NotFound, expected.to_payload()));
+    }
+
+    #[test]
+    fn list_routes() {
+        let mut server = Server::new();
+        assert!(server.is_empty());
+        server
+            .post("/bill", post_bill)
+            .get("/book", query_book)
+            .get("/", success)
+            .delete("/book/:id", book_by_id);
+
+        assert_eq!(
+            server.routes(),
+            vec![
+                (RequestType::Get, "/".to_string()),
+                (RequestType::Get, "/book".to_string()),
+                (RequestType::Post, "/bill".to_string()),
+                (RequestType::Delete, "/book/:id".to_string()),
+            ]
+        );
+        assert_eq!(server.len(), 4);
+        assert!(!server.is_empty());
+    }
+
+    #[test]
+    fn duplicate_route_rejected() {
+        let mut server = Server::new();
+        server.try_get("/x", success).unwrap();
+
+        let err = server.try_get("/x", book_title).err().unwrap();
+        assert_eq!(err, RouteError::Conflict { method: RequestType::Get, path: "/x".into() });
+        assert!(server.try_post("/x", success).is_ok());
+
+        let res = server.handle_request(Request::get("/x", vec![]));
+        assert_eq!(res, (HttpStatus::Success, vec![]));
+    }
+
+    #[test]
+    fn ambiguous_route_rejected() {
+        let mut server = Server::new();
+        server.try_get("/files/:name", named_file).unwrap();
+
+        let err = server.try_get("/files/*rest", file_tree).err().unwrap();
+        let existing = Path::from("/files/:name");
+        assert_eq!(err, RouteError::Ambiguous { method: RequestType::Get, path: "/files/*rest".into(), existing });
+        assert!(server.try_get("/files/readme", latest_book).is_ok());
+        assert!(server.try_post("/files/*rest", file_tree).is_ok());
+    }
+
+    #[test]
+    fn misplaced_wildcard_rejected() {
+        let mut server = Server::new();
+        let err = server.try_get("/a/*rest/b", file_tree).err().unwrap();
+        assert_eq!(err, RouteError::MisplacedWildcard { method: RequestType::Get, path: "/a/*rest/b".into() });
+        assert_eq!(server.handle_request(Request::get("/a/x", vec![])).0, HttpStatus::NotFound);
+
+        assert!(server.try_get("/a/*rest/", file_tree).is_ok());
+        assert_eq!(server.handle_request(Request::get("/a/x/y", vec![])).0, HttpStatus::Success);
+    }
+
+    #[test]
+    fn finalize_collects_route_errors() {
+        let mut server = Server::new();
+        server
+            .get("/x", success)
+            .get("/x", latest_book)
+            .get("/files/:name", named_file)
+            .post("/a//b", success)
+            .put("/users/:", success)
+            .patch("/a/*rest/b", file_tree)
+            .post("/files/*rest", file_tree);
+        assert!(server.replace(RequestType::Post, "/files/*rest", file_tree));
+
+        let errors = server.finalize().err().unwrap();
+        assert_eq!(errors, vec![
+            RouteError::Conflict { method: RequestType::Get, path: "/x".into() },
+            RouteError::EmptySegment { method: RequestType::Post, path: "/a//b".into() },
+            RouteError::EmptySegment { method: RequestType::Put, path: "/users/:".into() },
+            RouteError::MisplacedWildcard { method: RequestType::Patch, path: "/a/*rest/b".into() },
+        ]);
+
+        let mut server = Server::new();
+        server.get("/", success).get("/book/", latest_book).get("/files/:name", named_file).get("/files/readme", latest_book);
+        let server = server.finalize().unwrap();
+        assert_eq!(server.handle_request(Request::get("/", vec![])), (HttpStatus::Success, vec![]));
+    }
+
+    #[test]
+    fn finalize_allows_overriding_health_check() {
+        let mut server = Server::new().with_health_check("/healthz");
+        server.get("/healthz", || HttpStatus::Unauthorized);
+        server.try_get("/healthz", || HttpStatus::Created).err().unwrap();
+        let server = server.finalize().unwrap();
+        assert_eq!(server.handle_request(Request::get("/healthz", vec![])).0, HttpStatus::Unauthorized);
+
+        let mut server = Server::new().with_health_check("/healthz");
+        server.try_get("/healthz", || HttpStatus::Created).unwrap();
+        assert!(server.finalize().is_ok());
+    }
+
+    #[test]
+    fn finalize_reports_mounted_duplicates() {
+        let mut sub = Server::new();
+        sub.get("/x", success).get("/x", latest_book);
+
+        let mut server = Server::new();
+        server.mount("/api", sub).unwrap();
+        let errors = server.finalize().err().unwrap();
+        assert_eq!(errors, vec![RouteError::Conflict { method: RequestType::Get, path: "/api/x".into() }]);
+    }
+
+    #[test]
+    fn serve_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            let mut server = Server::new();
+            server.get("/book/title", book_title);
+            server.finalize().unwrap().serve_listener(listener)
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&request::encode(&Request::get("/book/title", 5usize.to_payload())).unwrap()).unwrap();
+        stream.write_all(&request::encode(&Request::get("/missing", vec![])).unwrap()).unwrap();
+
+        let mut read_response = || {
+            let mut header = [0u8; 6];
+            stream.read_exact(&mut header).unwrap();
+            let mut body = vec![0u8; u32::from_be_bytes(header[2..].try_into().unwrap()) as usize];
+            stream.read_exact(&mut body).unwrap();
+            (u16::from_be_bytes([header[0], header[1]]), body)
+        };
+
+        assert_eq!(read_response(), (200, "book no.5".to_string().to_payload()));
+        assert_eq!(read_response(), (404, "no route for /missing".to_string().to_payload()));
+    }
+
+    // Hands out at most three bytes per read, like a slow file or socket.
+    struct Trickle {
+        data: &'static [u8],
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.data.len()).min(3);
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn download(n: u8) -> Result<(HttpStatus, StreamBody), HttpStatus> {
+        match n {
+            0 => Ok((HttpStatus::Success, StreamBody::new(Trickle { data: b"gigabytes" }))),
+            1 => Ok((HttpStatus::Created, StreamBody::from_chunks([b"he".to_vec(), vec![], b"llo".to_vec()]))),
+            _ => Err(HttpStatus::NotFound),
+        }
+    }
+
+    #[test]
+    fn stream_response_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut server = Server::new();
+        server.get("/download", download);
+        assert_eq!(server.handle_request(Request::get("/download", 0u8.to_payload())), (HttpStatus::Success, b"gigabytes".to_vec()));
+        std::thread::spawn(move || server.finalize().unwrap().serve_listener(listener));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        for n in 0..3u8 {
+            stream.write_all(&request::encode(&Request::get("/download", n.to_payload())).unwrap()).unwrap();
+        }
+
+        let read_u32 = |stream: &mut TcpStream| {
+            let mut len = [0u8; 4];
+            stream.read_exact(&mut len).unwrap();
+            u32::from_be_bytes(len)
+        };
+        let read_streamed = |stream: &mut TcpStream| {
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).unwrap();
+            assert_eq!(read_u32(stream), u32::MAX);
+
+            let mut chunks = vec![];
+            loop {
+                let mut chunk = vec![0u8; read_u32(stream) as usize];
+                if chunk.is_empty() {
+                    break;
+                }
+                stream.read_exact(&mut chunk).unwrap();
+                chunks.push(String::from_utf8(chunk).unwrap());
+            }
+            (u16::from_be_bytes(status), chunks)
+        };
+
+        assert_eq!(read_streamed(&mut stream), (200, vec!["gig".into(), "aby".into(), "tes".into()]));
+        assert_eq!(read_streamed(&mut stream), (201, vec!["he".into(), "llo".into()]));
+
+        let mut response = [0u8; 6];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(response, [1, 148, 0, 0, 0, 0]);
+    }
+
+    async fn load_book(book_no: usize) -> Result<(HttpStatus, String), String> {
+        let title = async { format!("book no.{book_no}") }.await;
+
+        Ok((HttpStatus::Success, title))
+    }
+
+    #[test]
+    fn async_handlers() {
+        let mut server = Server::new();
+        server
+            .get_async("/book", load_book)
+            .post_async("/sum", |a: u32, b: u32| async move { (HttpStatus::Success, a + b) });
+
+        let res = server.handle_request(Request::get("/book", 2usize.to_payload()));
+        assert_eq!(res, (HttpStatus::Success, "book no.2".to_string().to_payload()));
+
+        let mut buf = 1u32.to_payload();
+        buf.extend(2u32.to_payload());
+        let res = server.handle_request(Request::post("/sum", buf));
+        assert_eq!(res, (HttpStatus::Success, 3u32.to_payload()));
+    }
+
+    #[test]
+    fn serve_concurrently() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+        let mut server = Server::new().with_workers(2);
+        server.get("/wait", move || {
+            barrier.wait();
+            HttpStatus::Success
+        });
+        std::thread::spawn(move || server.finalize().unwrap().serve_listener(listener));
+
+        // Both requests only complete if their handlers run at the same time.
+        let clients: Vec<_> = (0..2)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    let mut stream = TcpStream::connect(addr).unwrap();
+                    stream.write_all(&request::encode(&Request::get("/wait", vec![])).unwrap()).unwrap();
+                    let mut header = [0u8; 6];
+                    stream.read_exact(&mut header).unwrap();
+                    u16::from_be_bytes([header[0], header[1]])
+                })
+            })
+            .collect();
+
+        for client in clients {
+            assert_eq!(client.join().unwrap(), 200);
+        }
+    }
+
+    #[test]
+    fn max_payload_len() {
+        let mut server = Server::new().with_max_payload_len(8);
+        server.post("/sum", sum);
+
+        let res = server.handle_request(Request::post("/sum", vec![0; 9]));
+        assert_eq!(res.0, HttpStatus::PayloadTooLarge);
+
+        let res = server.handle_request(Request::get("/nope", vec![0; 9]));
+        assert_eq!(res.0, HttpStatus::NotFound);
+
+        let res = server.handle_request(Request::post("/sum", 9u32.to_payload()));
+        assert_eq!(res.0, HttpStatus::BadRequest);
+    }
+
+    struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<(String, HttpStatus)>>>);
+
+    impl Middleware for Recorder {
+        fn handle(&self, req: &Request, next: &dyn Fn(&Request) -> ServiceResponse) -> ServiceResponse {
+            let response = next(req);
+            self.0.lock().unwrap().push((req.path().as_str().to_string(), response.0));
+            response
+        }
+    }
+
+    #[test]
+    fn middleware_chain() {
+        let log = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+
+        let mut server = Server::new();
+        server
+            .get("/", success)
+            .get("/admin", success)
+            .wrap(Recorder(log.clone()))
+            .wrap(|req: &Request, next: &dyn Fn(&Request) -> ServiceResponse| {
+                if req.path().as_str().starts_with("/admin") {
+                    return (HttpStatus::Unauthorized, vec![]);
+                }
+                next(req)
+            });
+
+        assert_eq!(server.handle_request(Request::get("/", vec![])).0, HttpStatus::Success);
+        assert_eq!(server.handle_request(Request::get("/admin", vec![])).0, HttpStatus::Unauthorized);
+        assert_eq!(server.handle_request(Request::get("/missing", vec![])).0, HttpStatus::NotFound);
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                ("/".to_string(), HttpStatus::Success),
+                ("/admin".to_string(), HttpStatus::Unauthorized),
+                ("/missing".to_string(), HttpStatus::NotFound),
+            ]
+        );
+    }
+
+    struct Library {
+        titles: Vec<String>,
+    }
+
+    fn count_books(library: State<Library>) -> (HttpStatus, usize) {
+        (HttpStatus::Success, library.titles.len())
+    }
+
+    fn nth_title(library: State<Library>, n: usize) -> (HttpStatus, String) {
+        match library.titles.get(n) {
+            Some(title) => (HttpStatus::Success, title.clone()),
+            None => (HttpStatus::NotFound, String::new()),
+        }
+    }
+
+    #[test]
+    fn shared_state() {
+        let library = Library { titles: vec!["Dune".into(), "Emma".into()] };
+
+        let mut server = Server::new().with_state(library);
+        server.get("/books/count", count_books).get("/books/nth", nth_title);
+
+        let res = server.handle_request(Request::get("/books/count", vec![]));
+        assert_eq!(res, (HttpStatus::Success, 2usize.to_payload()));
+
+        let res = server.handle_request(Request::get("/books/nth", 1usize.to_payload()));
+        assert_eq!(res, (HttpStatus::Success, "Emma".to_string().to_payload()));
+
+        let mut stateless = Server::new();
+        stateless.get("/books/count", count_books);
+        let res = stateless.handle_request(Request::get("/books/count", vec![]));
+        assert_eq!(res.0, HttpStatus::InternalServerError);
+
+        let mut mistyped = Server::new().with_state(1u32);
+        mistyped.get("/books/count", count_books);
+        let res = mistyped.handle_request(Request::get("/books/count", vec![]));
+        assert_eq!(res.0, HttpStatus::InternalServerError);
+    }
+
+    struct Clock {
+        now: u64,
+    }
+
+    fn stamp(library: Inject<Library>, clock: Inject<Clock>) -> (HttpStatus, String) {
+        (HttpStatus::Success, format!("{} books at {}", library.titles.len(), clock.now))
+    }
+
+    #[test]
+    fn injected_dependencies() {
+        let library = std::sync::Arc::new(Library { titles: vec!["Dune".into()] });
+
+        let mut server = Server::new().inject(library.clone()).inject(std::sync::Arc::new(Clock { now: 7 }));
+        server.get("/stamp", stamp);
+        assert_eq!(TestClient::new(&server).get("/stamp").send(), (HttpStatus::Success, "1 books at 7".to_string().to_payload()));
+
+        let mut partial = Server::new().inject(library);
+        partial.get("/stamp", stamp);
+        let request = Request::get("/stamp", vec![]);
+        assert_eq!(partial.handle_request(request.clone()).0, HttpStatus::InternalServerError);
+        assert_eq!(partial.validate_request(&request), Err(PayloadError::MissingDependency(std::any::type_name::<Clock>())));
+
+        let errors = partial.finalize().err().unwrap();
+        let ty = std::any::type_name::<Clock>();
+        assert_eq!(errors, vec![RouteError::MissingDependency { method: RequestType::Get, path: "/stamp".into(), ty }]);
+        assert!(server.finalize().is_ok());
+    }
+
+    fn describe(method: Method, path: FullPath, id: usize) -> (HttpStatus, String) {
+        (HttpStatus::Success, format!("{} {} {id}", method.0, path.0))
+    }
+
+    #[test]
+    fn request_extractors() {
+        let mut server = Server::new();
+        server.route(RequestType::Put, "/book", describe);
+
+        let res = server.handle_request(Request::put("/book?draft=1", 7usize.to_payload()));
+        assert_eq!(res, (HttpStatus::Success, "put /book?draft=1 7".to_string().to_payload()));
+    }
+
+    fn shout(text: &str, times: u8) -> (HttpStatus, String) {
+        (HttpStatus::Success, text.to_uppercase().repeat(times as usize))
+    }
+
+    #[test]
+    fn borrowed_str_argument() {
+        let mut server = Server::new();
+        server.post("/shout", shout);
+
+        let mut payload = "hey".to_string().to_payload();
+        payload.push(2);
+        let res = server.handle_request(Request::post("/shout", payload));
+        assert_eq!(res, (HttpStatus::Success, "HEYHEY".to_string().to_payload()));
+    }
+
+    #[test]
+    fn strict_payload() {
+        let payload = [1usize.to_payload(), vec![0xff]].concat();
+
+        let mut lenient = Server::new();
+        lenient.get("/book", query_book).get("/", success);
+        assert_eq!(lenient.handle_request(Request::get("/book", payload.clone())).0, HttpStatus::Success);
+
+        let mut strict = Server::new().strict_payload(true);
+        strict.get("/book", query_book).get("/", success);
+        assert_eq!(strict.handle_request(Request::get("/book", payload)).0, HttpStatus::BadRequest);
+        assert_eq!(strict.handle_request(Request::get("/", vec![0])).0, HttpStatus::BadRequest);
+        assert_eq!(strict.handle_request(Request::get("/book", 1usize.to_payload())).0, HttpStatus::Success);
+    }
+
+    fn register(addr: std::net::SocketAddr, weight: u16) -> (HttpStatus, String) {
+        (HttpStatus::Success, format!("{addr}/{weight}"))
+    }
+
+    #[test]
+    fn socket_addr_argument() {
+        let mut server = Server::new();
+        server.post("/nodes", register);
+
+        let addr: std::net::SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let payload = [addr.to_payload(), 3u16.to_payload()].concat();
+        let res = server.handle_request(Request::post("/nodes", payload));
+        assert_eq!(res, (HttpStatus::Success, "127.0.0.1:9000/3".to_string().to_payload()));
+    }
+
+    #[test]
+    fn request_metrics() {
+        use crate::metrics::Counts;
+
+        let mut server = Server::new();
+        server.get("/book", query_book).get("/book/:id", book_by_id);
+
+        server.handle_request(Request::get("/book", 1usize.to_payload()));
+        server.handle_request(Request::get("/book", vec![]));
+        server.handle_request(Request::get("/book/1", vec![]));
+        server.handle_request(Request::get("/book/2", vec![]));
+        server.handle_request(Request::get("/missing", vec![]));
+
+        let metrics = server.metrics();
+        assert_eq!(metrics.total, Counts { served: 5, failed: 2 });
+        assert_eq!(metrics.routes[&(RequestType::Get, "/book".into())], Counts { served: 2, failed: 1 });
+        assert_eq!(metrics.routes[&(RequestType::Get, "/book/:id".into())], Counts { served: 2, failed: 0 });
+        assert_eq!(metrics.routes.len(), 2);
+    }
+
+    #[test]
+    fn handler_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut server = Server::new().with_workers(2).handler_timeout(std::time::Duration::from_millis(50));
+        server
+            .get("/slow", || {
+                std::thread::sleep(std::time::Duration::from_millis(500));
+                HttpStatus::Success
+            })
+            .get("/", success);
+        std::thread::spawn(move || server.finalize().unwrap().serve_listener(listener));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut status = |path: &str| {
+            stream.write_all(&request::encode(&Request::get(path, vec![])).unwrap()).unwrap();
+            let mut header = [0u8; 6];
+            stream.read_exact(&mut header).unwrap();
+            u16::from_be_bytes([header[0], header[1]])
+        };
+
+        assert_eq!(status("/slow"), 504);
+        assert_eq!(status("/"), 200);
+    }
+
+    #[test]
+    fn graceful_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let started_tx = std::sync::Mutex::new(started_tx);
+        let mut server = Server::new();
+        server.get("/slow", move || {
+            started_tx.lock().unwrap().send(()).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            HttpStatus::Success
+        });
+
+        let shutdown = Shutdown::new();
+        let serving = {
+            let shutdown = shutdown.clone();
+            std::thread::spawn(move || server.finalize().unwrap().serve_until(listener, shutdown))
+        };
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&request::encode(&Request::get("/slow", vec![])).unwrap()).unwrap();
+        started_rx.recv().unwrap();
+        shutdown.trigger();
+
+        // The request in flight is still answered before the connection closes.
+        let mut response = vec![];
+        stream.read_to_end(&mut response).unwrap();
+        assert_eq!(response, [0, 200, 0, 0, 0, 0]);
+
+        serving.join().unwrap().unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn case_insensitive_routes() {
+        let mut server = Server::new().case_insensitive(true);
+        server.get("/Book/title", book_title).get("/book/:id", book_by_id);
+
+        let res = server.handle_request(Request::get("/BOOK/Title", 5usize.to_payload()));
+        assert_eq!(res, (HttpStatus::Success, "book no.5".to_string().to_payload()));
+        assert_eq!(server.handle_request(Request::get("/bOoK/42", vec![])).0, HttpStatus::Success);
+        assert!(server.try_get("/BOOK/TITLE", book_title).is_err());
+
+        let mut sensitive = Server::new();
+        sensitive.get("/book/title", book_title);
+        assert_eq!(sensitive.handle_request(Request::get("/Book/title", 5usize.to_payload())).0, HttpStatus::NotFound);
+        assert!(sensitive.try_get("/Book/title", book_title).is_ok());
+    }
+
+    #[test]
+    fn case_insensitive_after_registering() {
+        let mut server = Server::new();
+        server.get("/Book/title", book_title).limit("/Book/title", 8);
+        let server = server.case_insensitive(true);
+        let client = TestClient::new(&server);
+        assert_eq!(client.get("/Book/title").field(5usize).send().0, HttpStatus::Success);
+        assert_eq!(client.get("/book/TITLE").field(5usize).send().0, HttpStatus::Success);
+        assert_eq!(client.get("/book/title").field(5usize).field(0u8).send().0, HttpStatus::PayloadTooLarge);
+
+        let server = server.case_insensitive(false);
+        let client = TestClient::new(&server);
+        assert_eq!(client.get("/Book/title").field(5usize).send().0, HttpStatus::Success);
+        assert_eq!(client.get("/book/title").field(5usize).send().0, HttpStatus::NotFound);
+    }
+
+    #[test]
+    fn case_only_conflicts_panic() {
+        let mut server = Server::new().case_insensitive(true);
+        server.get("/book", success).get("/book", latest_book);
+        assert_eq!(server.len(), 1);
+
+        let register = || {
+            let mut server = Server::new().case_insensitive(true);
+            server.get("/Book", success).get("/book", latest_book);
+        };
+        assert!(std::panic::catch_unwind(register).is_err());
+
+        let rekey = || {
+            let mut server = Server::new();
+            server.get("/Book", success).get("/book", latest_book);
+            server.case_insensitive(true)
+        };
+        assert!(std::panic::catch_unwind(rekey).is_err());
+    }
+
+    #[test]
+    fn trailing_slash_policies() {
+        let policies = [TrailingSlash::Strict, TrailingSlash::Ignore, TrailingSlash::RedirectToCanonical];
+        let servers: Vec<Server> = policies
+            .into_iter()
+            .map(|policy| {
+                let mut server = Server::new().trailing_slash(policy);
+                server.get("/book", success).get("/shelf/", success).get("/", success);
+                server
+            })
+            .collect();
+        let [strict, ignore, redirect] = &servers[..] else { unreachable!() };
+
+        let status = |server: &Server, path: &str| server.handle_request(Request::get(path, vec![]));
+
+        assert_eq!(status(strict, "/book").0, HttpStatus::Success);
+        assert_eq!(status(strict, "/book/").0, HttpStatus::NotFound);
+
+        assert_eq!(status(ignore, "/book/").0, HttpStatus::Success);
+        assert_eq!(status(ignore, "/shelf").0, HttpStatus::Success);
+        assert_eq!(status(ignore, "/").0, HttpStatus::Success);
+
+        assert_eq!(status(redirect, "/book/"), (HttpStatus::PermanentRedirect, "/book".to_string().to_payload()));
+        assert_eq!(status(redirect, "/shelf"), (HttpStatus::PermanentRedirect, "/shelf/".to_string().to_payload()));
+        assert_eq!(status(redirect, "/book").0, HttpStatus::Success);
+        assert_eq!(status(redirect, "/missing/").0, HttpStatus::NotFound);
+    }
+
+    #[test]
+    fn cloned_server() {
+        let mut server = Server::new();
+        server.get("/book/title", book_title).get("/book/:id", book_by_id);
+        let clone = server.clone();
+
+        for server in [&server, &clone] {
+            let res = server.handle_request(Request::get("/book/title", 3usize.to_payload()));
+            assert_eq!(res, (HttpStatus::Success, "book no.3".to_string().to_payload()));
+            let res = server.handle_request(Request::get("/book/7", vec![]));
+            assert_eq!(res, (HttpStatus::Success, "param 7".to_string().to_payload()));
+        }
+        assert_eq!(server.metrics().total.served, 4);
+    }
+
+    fn passthrough(Raw(request): Raw) -> Result<(HttpStatus, Vec<u8>), PayloadError> {
+        request::encode(&request).map(|frame| (HttpStatus::Success, frame))
+    }
+
+    #[test]
+    fn raw_request_handler() {
+        let mut server = Server::new().strict_payload(true);
+        server
+            .post("/proxy/*rest", passthrough)
+            .post("/tagged", |tag: u8, Raw(request): Raw| (HttpStatus::Success, vec![tag, request.payload.len() as u8]));
+
+        let request = Request::post("/proxy/a?x=1", vec![1, 2, 3]);
+        let res = server.handle_request(request.clone());
+        assert_eq!(res, (HttpStatus::Success, request::encode(&request).unwrap().to_payload()));
+
+        let res = server.handle_request(Request::post("/tagged", vec![9, 8, 7]));
+        assert_eq!(res, (HttpStatus::Success, vec![9u8, 3].to_payload()));
+    }
+
+    fn describe_shape(shape: Shape, command: Command) -> (HttpStatus, String) {
+        (HttpStatus::Success, format!("{shape:?} {command:?}"))
+    }
+
+    #[test]
+    fn derived_enum_argument() {
+        let mut server = Server::new();
+        server.post("/shape", describe_shape);
+        let shape = |payload: Vec<u8>| server.handle_request(Request::post("/shape", payload));
+
+        let circle = (Shape::Circle { radius: 2.5 }, Command::Stop).to_payload();
+        assert_eq!(circle, [vec![0], 2.5f32.to_payload(), 0x10u32.to_payload()].concat());
+        assert_eq!(shape(circle), (HttpStatus::Success, "Circle { radius: 2.5 } Stop".to_string().to_payload()));
+
+        let rect = (Shape::Rect(3, 4), Command::Move(-1)).to_payload();
+        assert_eq!(rect, [vec![1], 3u16.to_payload(), 4u16.to_payload(), 0x11u32.to_payload(), vec![0xff]].concat());
+        assert_eq!(shape(rect), (HttpStatus::Success, "Rect(3, 4) Move(-1)".to_string().to_payload()));
+
+        assert_eq!(shape(vec![2]).0, HttpStatus::BadRequest);
+        assert_eq!(shape([vec![1, 0, 0, 0, 0], 0u32.to_payload()].concat()).0, HttpStatus::BadRequest);
+    }
+
+    #[test]
+    fn test_client() {
+        let mut server = Server::new();
+        server.post("/shape", describe_shape).get("/book/:id", book_by_id);
+        let client = TestClient::new(&server);
+
+        let (status, body) = client.post("/shape").send_fields((Shape::Rect(3, 4), Command::Stop));
+        assert_eq!((status, body), (HttpStatus::Success, "Rect(3, 4) Stop".to_string().to_payload()));
+        assert_eq!(client.post("/shape").field(2u8).send().0, HttpStatus::BadRequest);
+        assert_eq!(client.delete("/shape").send().0, HttpStatus::MethodNotAllowed);
+        assert_eq!(client.get("/book/7").send(), (HttpStatus::Success, "param 7".to_string().to_payload()));
+    }
+
+    #[test]
+    fn completion_hook() {
+        let log = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let mut server = Server::new();
+        server.get("/book", success);
+        let entries = log.clone();
+        server.on_complete(move |info| entries.lock().unwrap().push((info.id, info.method, info.path.clone(), info.status)));
+
+        server.handle_request(Request::get("/book", vec![]));
+        server.handle_request(Request::post("/missing", vec![]));
+
+        assert_eq!(*log.lock().unwrap(), vec![
+            (RequestId(1), RequestType::Get, Path::from("/book"), HttpStatus::Success),
+            (RequestId(2), RequestType::Post, Path::from("/missing"), HttpStatus::NotFound),
+        ]);
+    }
+
+    #[test]
+    fn request_ids() {
+        let mut server = Server::new();
+        server.get("/id", |id: RequestId| (HttpStatus::Success, id.0));
+        let client = TestClient::new(&server);
+
+        let (first, second) = (client.get("/id").send(), client.get("/id").send());
+        assert_eq!(first, (HttpStatus::Success, 1u64.to_payload()));
+        assert_eq!(second, (HttpStatus::Success, 2u64.to_payload()));
+    }
+
+    #[test]
+    fn handler_on_several_methods() {
+        let mut server = Server::new();
+        server.on(&[RequestType::Get, RequestType::Post], "/echo", |method: Method, x: u32| (HttpStatus::Success, format!("{} {x}", method.0)));
+        let client = TestClient::new(&server);
+
+        assert_eq!(client.get("/echo").send_fields(1u32), (HttpStatus::Success, "get 1".to_string().to_payload()));
+        assert_eq!(client.post("/echo").send_fields(2u32), (HttpStatus::Success, "post 2".to_string().to_payload()));
+        assert_eq!(client.put("/echo").send_fields(3u32).0, HttpStatus::MethodNotAllowed);
+    }
+
+    #[test]
+    fn route_table_macro() {
+        let table = crate::routes! {
+            GET "/" => success,
+            GET "/book" => query_book,
+            POST "/bill" => post_bill,
+            DELETE "/book/:id" => book_by_id,
+        };
+
+        let mut chained = Server::new();
+        chained.get("/", success).get("/book", query_book).post("/bill", post_bill).delete("/book/:id", book_by_id);
+
+        let mut routes = table.routes();
+        routes.sort();
+        let mut expected = chained.routes();
+        expected.sort();
+        assert_eq!(routes, expected);
+
+        let requests = [
+            Request::get("/book", 7usize.to_payload()),
+            Request::post("/bill", [1usize.to_payload(), 2.5f32.to_payload()].concat()),
+            Request::delete("/book/3", vec![]),
+            Request::patch("/", vec![]),
+        ];
+        for request in requests {
+            assert_eq!(table.handle_request(request.clone()), chained.handle_request(request));
+        }
+    }
+
+    #[test]
+    fn matched_path_argument() {
+        let mut server = Server::new();
+        server
+            .get("/book/:id/chapter/:chapter", |matched: MatchedPath| {
+                (HttpStatus::Success, format!("{} {}", matched.pattern, matched.params.get("chapter").unwrap()))
+            })
+            .fallback(|_: MatchedPath| HttpStatus::Success);
+        let client = TestClient::new(&server);
+
+        let res = client.get("/book/7/chapter/2").send();
+        assert_eq!(res, (HttpStatus::Success, "/book/:id/chapter/:chapter 2".to_string().to_payload()));
+        assert_eq!(client.get("/elsewhere").send().0, HttpStatus::BadRequest);
+    }
+
+    #[test]
+    fn status_messages() {
+        let mut server = Server::new();
+        server.get("/book", |id: Option<u32>| match id {
+            Some(_) => HttpStatus::Success.with_message(""),
+            None => HttpStatus::BadRequest.with_message("missing field id"),
+        });
+        let client = TestClient::new(&server);
+
+        assert_eq!(client.get("/book").send_fields(None::<u32>), (HttpStatus::BadRequest, "missing field id".to_string().to_payload()));
+        assert_eq!(client.get("/shelf").send(), (HttpStatus::NotFound, "no route for /shelf".to_string().to_payload()));
+    }
+
+    fn read_file(path: String) -> Result<(HttpStatus, Vec<u8>), HttpStatus> {
+        let bytes = std::fs::read(path)?;
+        Ok((HttpStatus::Success, bytes))
+    }
+
+    #[test]
+    fn io_error_handler() {
+        let mut server = Server::new();
+        server
+            .get("/file", read_file)
+            .get("/io", |path: String| std::fs::read(path).map(|_| HttpStatus::Success))
+            .get("/denied", || Err::<HttpStatus, _>(HttpStatus::Unauthorized));
+        let client = TestClient::new(&server);
+
+        let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml").to_string();
+        assert_eq!(client.get("/file").send_fields(manifest.clone()).0, HttpStatus::Success);
+        assert_eq!(client.get("/file").send_fields("/no/such/file").0, HttpStatus::InternalServerError);
+        assert_eq!(client.get("/io").send_fields(manifest).0, HttpStatus::Success);
+        assert_eq!(client.get("/io").send_fields("/no/such/file").0, HttpStatus::InternalServerError);
+        assert_eq!(client.get("/denied").send(), (HttpStatus::Unauthorized, vec![]));
+    }
+
+    #[test]
+    fn stateful_handler() {
+        let mut total = 0;
+        let mut server = Server::new();
+        server.post("/add", crate::handler::Stateful::new(move |x: u32| {
+            total += x;
+            (HttpStatus::Success, total)
+        }));
+        let client = TestClient::new(&server);
+
+        for (x, expected) in [(1u32, 1u32), (2, 3), (4, 7)] {
+            assert_eq!(client.post("/add").send_fields(x), (HttpStatus::Success, expected.to_payload()));
+        }
+    }
+
+    #[test]
+    fn stateful_handler_survives_panic() {
+        let mut calls = 0u32;
+        let mut server = Server::new();
+        server.post("/count", crate::handler::Stateful::new(move |fail: bool| {
+            calls += 1;
+            assert!(!fail, "handler failed");
+            (HttpStatus::Success, calls)
+        }));
+        let client = TestClient::new(&server);
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| client.post("/count").field(true).send()));
+        assert!(panicked.is_err());
+        assert_eq!(client.post("/count").field(false).send(), (HttpStatus::Success, 2u32.to_payload()));
+    }
+
+    #[test]
+    fn checksummed_frames_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        std::thread::spawn(move || {
+            let mut server = Server::new().verify_checksum(true);
+            server.get("/book/title", book_title);
+            server.finalize().unwrap().serve_listener(listener)
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let request = Request::get("/book/title", 5usize.to_payload());
+        stream.write_all(&request::encode_with_checksum(&request).unwrap()).unwrap();
+        let mut header = [0u8; 6];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(u16::from_be_bytes([header[0], header[1]]), 200);
+        let mut body = vec![0u8; u32::from_be_bytes(header[2..].try_into().unwrap()) as usize];
+        stream.read_exact(&mut body).unwrap();
+
+        let mut corrupted = request::encode_with_checksum(&request).unwrap();
+        corrupted[3] ^= 0x20;
+        stream.write_all(&corrupted).unwrap();
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(u16::from_be_bytes([header[0], header[1]]), 400);
+        let mut body = vec![0u8; u32::from_be_bytes(header[2..].try_into().unwrap()) as usize];
+        stream.read_exact(&mut body).unwrap();
+        let reason = <String as FromPayload>::from(&mut crate::service::Payload::from_bytes(&body)).unwrap();
+        assert!(reason.starts_with("checksum "), "{reason}");
+        assert_eq!(stream.read(&mut header).unwrap(), 0);
+
+        // A truncated frame gets no response, only the closed connection.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let frame = request::encode_with_checksum(&request).unwrap();
+        stream.write_all(&frame[..frame.len() - 1]).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        assert_eq!(stream.read(&mut header).unwrap(), 0);
+    }
+
+    fn typed_book(id: u32) -> Response<(u32, String)> {
+        match id {
+            0 => Response::new(HttpStatus::NotFound, (id, String::new())),
+            _ => Response::ok((id, format!("book no.{id}"))),
+        }
+    }
+
+    #[test]
+    fn typed_response() {
+        let mut server = Server::new();
+        server.get("/book", typed_book).get("/", success);
+        let client = TestClient::new(&server);
+
+        assert_eq!(client.get("/book").send_fields(3u32), (HttpStatus::Ok, (3u32, "book no.3".to_string()).to_payload()));
+        assert_eq!(client.get("/book").send_fields(0u32).0, HttpStatus::NotFound);
+        assert_eq!(client.get("/").send(), (HttpStatus::Ok, vec![]));
+    }
+
+    #[test]
+    fn route_payload_limits() {
+        let mut server = Server::new().with_max_payload_len(8);
+        server
+            .post("/upload", |body: crate::service::RawBody| (HttpStatus::Success, body.0.len() as u32))
+            .post("/tiny", |_: crate::service::RawBody| HttpStatus::Success)
+            .post("/bill", post_bill)
+            .limit("/upload", 64)
+            .limit("/tiny", 2);
+        let client = TestClient::new(&server);
+
+        assert_eq!(client.post("/upload").field([0u8; 64]).send(), (HttpStatus::Success, 64u32.to_payload()));
+        assert_eq!(client.post("/upload").field([0u8; 65]).send().0, HttpStatus::PayloadTooLarge);
+        assert_eq!(client.post("/tiny").field([0u8; 3]).send().0, HttpStatus::PayloadTooLarge);
+        assert_eq!(client.post("/bill").field([0u8; 9]).send().0, HttpStatus::PayloadTooLarge);
+        assert_eq!(client.post("/missing").field([0u8; 9]).send().0, HttpStatus::NotFound);
+        assert_eq!(client.get("/upload").field([0u8; 65]).send().0, HttpStatus::MethodNotAllowed);
+    }
+
+    #[test]
+    fn health_check() {
+        let server = Server::new().strict_payload(true).with_health_check("/healthz");
+        let client = TestClient::new(&server);
+        assert_eq!(client.get("/healthz").send(), (HttpStatus::Ok, vec![]));
+        assert_eq!(client.get("/healthz").field(1u32).send(), (HttpStatus::Ok, vec![]));
+
+        let mut overridden = Server::new().with_health_check("/healthz");
+        overridden.get("/healthz", || HttpStatus::Unauthorized);
+        assert_eq!(TestClient::new(&overridden).get("/healthz").send().0, HttpStatus::Unauthorized);
+
+        let mut registered = Server::new();
+        registered.get("/healthz", || HttpStatus::Created);
+        let registered = registered.with_health_check("/healthz");
+        assert_eq!(TestClient::new(&registered).get("/healthz").send().0, HttpStatus::Created);
+    }
+
+    #[test]
+    fn empty_path_routes_to_root() {
+        let mut server = Server::new();
+        server.get("", success);
+        let client = TestClient::new(&server);
+
+        assert_eq!(server.routes(), vec![(RequestType::Get, "/".to_string())]);
+        for path in ["", "/", "   "] {
+            assert_eq!(client.get(path).send().0, HttpStatus::Success);
+        }
+    }
+
+    #[test]
+    fn remove_and_replace_routes() {
+        let mut server = Server::new();
+        server.get("/book/:id", book_by_id).get("/book", success);
+        let client = |server: &Server, path: &str| TestClient::new(server).get(path).send();
+
+        assert!(server.replace(RequestType::Get, "/book/:id", || HttpStatus::Created));
+        assert_eq!(client(&server, "/book/1").0, HttpStatus::Created);
+        assert!(!server.replace(RequestType::Post, "/book/:id", success));
+        assert_eq!(server.len(), 2);
+
+        assert!(server.remove(RequestType::Get, "/book/:id"));
+        assert!(!server.remove(RequestType::Get, "/book/:id"));
+        assert_eq!(client(&server, "/book/1").0, HttpStatus::NotFound);
+        assert_eq!(client(&server, "/book").0, HttpStatus::Success);
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Color {
+        Red,
+        Green,
+        Blue,
+    }
+
+    impl TryFrom<u32> for Color {
+        type Error = ();
+
+        fn try_from(code: u32) -> Result<Self, ()> {
+            match code {
+                1 => Ok(Color::Red),
+                2 => Ok(Color::Green),
+                4 => Ok(Color::Blue),
+                _ => Err(()),
+            }
+        }
+    }
+
+    impl From<Color> for u32 {
+        fn from(color: Color) -> u32 {
+            match color {
+                Color::Red => 1,
+                Color::Green => 2,
+                Color::Blue => 4,
+            }
+        }
+    }
+
+    crate::from_payload_via_try_from!(Color);
+    crate::to_payload_via_into!(Color);
+
+    // Only `TryFrom<u32>`, so it can be decoded but not encoded.
+    #[derive(Debug, PartialEq)]
+    struct Level(u8);
+
+    impl TryFrom<u32> for Level {
+        type Error = ();
+
+        fn try_from(code: u32) -> Result<Self, ()> {
+            u8::try_from(code).map(Level).map_err(|_| ())
+        }
+    }
+
+    crate::from_payload_via_try_from!(Level);
+
+    #[test]
+    fn try_from_code_argument() {
+        let mut server = Server::new();
+        server.post("/paint", |color: Color| (HttpStatus::Success, format!("{color:?}")));
+        let client = TestClient::new(&server);
+
+        for (color, name) in [(Color::Red, "Red"), (Color::Green, "Green"), (Color::Blue, "Blue")] {
+            assert_eq!(client.post("/paint").send_fields(color), (HttpStatus::Success, name.to_payload()));
+        }
+        assert_eq!(Color::Blue.to_payload(), 4u32.to_payload());
+        assert_eq!(client.post("/paint").send_fields(3u32).0, HttpStatus::BadRequest);
+
+        let decoded = <Color as FromPayload>::from(&mut crate::service::Payload::from_bytes(&3u32.to_payload()));
+        assert_eq!(decoded, Err(crate::service::PayloadError::InvalidDiscriminant(3)));
+
+        let level = <Level as FromPayload>::from(&mut crate::service::Payload::from_bytes(&7u32.to_payload()));
+        assert_eq!(level, Ok(Level(7)));
+    }
+
+    #[test]
+    fn deadline_argument() {
+        let remaining = |deadline: Deadline| (HttpStatus::Success, deadline.remaining().map(|d| d.as_millis() as u64));
+
+        let mut server = Server::new().handler_timeout(std::time::Duration::from_secs(60));
+        server.get("/deadline", remaining);
+        let (_, body) = TestClient::new(&server).get("/deadline").send();
+        let left = <Option<u64> as FromPayload>::from(&mut crate::service::Payload::from_bytes(&body)).unwrap();
+        assert!(left.is_some_and(|ms| ms > 50_000 && ms <= 60_000));
+
+        let mut server = Server::new();
+        server.get("/deadline", remaining);
+        assert_eq!(TestClient::new(&server).get("/deadline").send(), (HttpStatus::Success, None::<u64>.to_payload()));
+        assert!(!Deadline(None).is_expired());
+    }
+
+    #[test]
+    fn deadline_counts_time_queued() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let started_tx = std::sync::Mutex::new(started_tx);
+        let mut server = Server::new().with_workers(1).handler_timeout(std::time::Duration::from_secs(1));
+        server
+            .get("/slow", move || {
+                started_tx.lock().unwrap().send(()).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(300));
+                HttpStatus::Success
+            })
+            .get("/deadline", |deadline: Deadline| (HttpStatus::Success, deadline.remaining().map(|d| d.as_millis() as u64)));
+        std::thread::spawn(move || server.finalize().unwrap().serve_listener(listener));
+
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.write_all(&request::encode(&Request::get("/slow", vec![])).unwrap()).unwrap();
+        started_rx.recv().unwrap();
+
+        // The only worker is busy, so this request waits in the queue first.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(&request::encode(&Request::get("/deadline", vec![])).unwrap()).unwrap();
+        let mut header = [0u8; 6];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(u16::from_be_bytes([header[0], header[1]]), 200);
+        let mut body = vec![0; u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize];
+        stream.read_exact(&mut body).unwrap();
+
+        let left = <Option<u64> as FromPayload>::from(&mut crate::service::Payload::from_bytes(&body)).unwrap();
+        assert!(left.is_some_and(|ms| ms <= 800));
+    }
+
+    #[test]
+    fn zero_argument_handler_with_payload() {
+        let mut strict = Server::new().strict_payload(true);
+        strict.get("/", success).get("/unit", |_: ()| HttpStatus::Success);
+        let client = TestClient::new(&strict);
+
+        assert_eq!(client.get("/").send().0, HttpStatus::Success);
+        assert_eq!(client.get("/").field(7u8).send().0, HttpStatus::BadRequest);
+        assert_eq!(client.get("/unit").send().0, HttpStatus::Success);
+        assert_eq!(client.get("/unit").field(7u8).send().0, HttpStatus::BadRequest);
+
+        let mut lenient = Server::new();
+        lenient.get("/unit", |_: ()| HttpStatus::Success);
+        assert_eq!(TestClient::new(&lenient).get("/unit").field(7u8).send().0, HttpStatus::Success);
+    }
+
+    #[test]
+    fn unread_payload_hook() {
+        let unread = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let reported = unread.clone();
+
+        let mut server = Server::new();
+        server
+            .get("/unit", |_: ()| HttpStatus::Success)
+            .get("/byte", |_: u8| HttpStatus::Success)
+            .on_unread_payload(move |path, n| reported.lock().unwrap().push((path.to_string(), n)));
+        let client = TestClient::new(&server);
+
+        assert_eq!(client.get("/unit").field(7u32).send().0, HttpStatus::Success);
+        assert_eq!(client.get("/byte").field(7u8).send().0, HttpStatus::Success);
+        assert_eq!(client.get("/byte").field(7u16).send().0, HttpStatus::Success);
+        assert_eq!(*unread.lock().unwrap(), vec![("/unit".to_string(), 4), ("/byte".to_string(), 1)]);
+
+        // Validation is a dry run, so the hook hears nothing of it.
+        assert_eq!(server.validate_request(&Request::get("/unit", 7u32.to_payload())), Ok(()));
+        assert_eq!(unread.lock().unwrap().len(), 2);
+    }
+
+    fn sign(key: u8, body: &[u8]) -> (HttpStatus, u8) {
+        (HttpStatus::Success, body.iter().fold(key, |acc, b| acc ^ b))
+    }
+
+    #[test]
+    fn borrowed_bytes_argument() {
+        let mut server = Server::new().strict_payload(true);
+        server.post("/sign", sign);
+        let client = TestClient::new(&server);
+
+        assert_eq!(client.post("/sign").field(0xf0u8).field(&[0x01u8, 0x02, 0x04][..]).send(), (HttpStatus::Success, 0xf7u8.to_payload()));
+        assert_eq!(client.post("/sign").field(0xf0u8).send(), (HttpStatus::Success, 0xf0u8.to_payload()));
+    }
+
+    #[test]
+    fn validate_request_without_calling_handler() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let mut server = Server::new().strict_payload(true);
+        server.post("/users/:id", |_: Params, _: u32| {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            HttpStatus::Success
+        });
+
+        let request = |path: &str, payload: Vec<u8>| Request::new(RequestType::Post, path, payload);
+        assert_eq!(server.validate_request(&request("/users/1", 5u32.to_payload())), Ok(()));
+        assert_eq!(server.validate_request(&request("/users/1", vec![1])), Err(PayloadError::UnexpectedEof { needed: 4, remaining: 1 }));
+        assert_eq!(server.validate_request(&request("/users/1", vec![0; 5])), Err(PayloadError::TrailingBytes(1)));
+        assert_eq!(server.validate_request(&request("/posts/1", vec![])), Err(PayloadError::NoRoute));
+        assert_eq!(CALLS.load(Ordering::SeqCst), 0);
+    }
 }
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+use crate::request::{Path, RequestType};
+use crate::service::HttpStatus;
+
+/// Request counts of one route, or of the whole server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub served: u64,
+    pub failed: u64,
+}
+
+/// Snapshot of the request counters of a `Server`. Routes are keyed by the
+/// pattern they were registered with. A response with a 4xx or 5xx status
+/// counts as failed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub total: Counts,
+    pub routes: HashMap<(RequestType, Path), Counts>,
+}
+
+#[derive(Default)]
+struct Counters {
+    served: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl Counters {
+    fn record(&self, status: HttpStatus) {
+        self.served.fetch_add(1, Ordering::Relaxed);
+        if status.code() >= 400 {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn counts(&self) -> Counts {
+        Counts { served: self.served.load(Ordering::Relaxed), failed: self.failed.load(Ordering::Relaxed) }
+    }
+}
+
+// Counters are only write-locked the first time a route is hit.
+#[derive(Default)]
+pub(crate) struct Recorder {
+    total: Counters,
+    routes: RwLock<HashMap<(RequestType, Path), Counters>>,
+}
+
+impl Recorder {
+    pub(crate) fn record_total(&self, status: HttpStatus) {
+        self.total.record(status);
+    }
+
+    pub(crate) fn record_route(&self, method: RequestType, pattern: &Path, status: HttpStatus) {
+        let key = (method, pattern.clone());
+        if let Some(counters) = self.routes.read().unwrap().get(&key) {
+            counters.record(status);
+            return;
+        }
+
+        self.routes.write().unwrap().entry(key).or_default().record(status);
+    }
+
+    pub(crate) fn snapshot(&self) -> Metrics {
+        let routes = self.routes.read().unwrap().iter().map(|(key, counters)| (key.clone(), counters.counts())).collect();
+        Metrics { total: self.total.counts(), routes }
+    }
+}
@@ -0,0 +1,27 @@
+use crate::request::Request;
+use crate::service::ServiceResponse;
+
+/// Runs around every request handled by a `Server`. Calling `next` passes
+/// the request on to the next middleware, or to the matched service after
+/// the last one. Returning without calling `next` short-circuits the request.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, req: &Request, next: &dyn Fn(&Request) -> ServiceResponse) -> ServiceResponse;
+}
+
+impl<F> Middleware for F
+where F: Fn(&Request, &dyn Fn(&Request) -> ServiceResponse) -> ServiceResponse + Send + Sync
+{
+    fn handle(&self, req: &Request, next: &dyn Fn(&Request) -> ServiceResponse) -> ServiceResponse {
+        (self)(req, next)
+    }
+}
+
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle(&self, req: &Request, next: &dyn Fn(&Request) -> ServiceResponse) -> ServiceResponse {
+        let response = next(req);
+        println!("{} {} -> {}", req.method(), req.path().as_str(), response.0.code());
+        response
+    }
+}
@@ -0,0 +1,119 @@
+use crate::service::{self, ToPayload};
+
+/// Assembles a payload field by field, encoding each one exactly as the
+/// matching `FromPayload` impl decodes it. Numbers are in native byte order,
+/// the server default; wrap them in `Be` or `Le` for a fixed order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PayloadBuilder {
+    bytes: Vec<u8>,
+}
+
+macro_rules! push_basic_type {
+    ($($name: ident($T: ident)),+) => {$(
+        pub fn $name(self, x: $T) -> Self {
+            self.push(x)
+        }
+    )+};
+}
+
+impl PayloadBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends any field type with a `ToPayload` impl.
+    pub fn push(mut self, field: impl ToPayload) -> Self {
+        self.bytes.extend(field.to_payload());
+        self
+    }
+
+    push_basic_type!(
+        push_u8(u8), push_u16(u16), push_u32(u32), push_u64(u64),
+        push_i8(i8), push_i16(i16), push_i32(i32), push_i64(i64),
+        push_f32(f32), push_f64(f64),
+        push_bool(bool), push_char(char)
+    );
+
+    /// A `u32` length prefix followed by the bytes of `s`.
+    pub fn push_string(self, s: &str) -> Self {
+        self.push(s)
+    }
+
+    /// A `u32` count prefix followed by each item. Panics if there are more
+    /// than `u32::MAX` items.
+    pub fn push_vec<T: ToPayload>(mut self, items: &[T]) -> Self {
+        self.bytes.extend(service::len_prefix(items.len()));
+        for item in items {
+            self.bytes.extend(item.to_payload());
+        }
+        self
+    }
+
+    /// A `0` byte for `None`, or a `1` byte followed by the value.
+    pub fn push_option<T: ToPayload>(self, x: Option<T>) -> Self {
+        self.push(x)
+    }
+
+    /// Appends `bytes` as they are, with no length prefix.
+    pub fn push_raw(mut self, bytes: &[u8]) -> Self {
+        self.bytes.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+    use crate::service::{Be, FromPayload, Payload, RawBody, Varint};
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let map: HashMap<u8, char> = [(1, 'a')].into();
+        let bytes = PayloadBuilder::new()
+            .push_u32(10)
+            .push_string("name")
+            .push_vec(&[1u16, 2, 3])
+            .push_option(Some(-4i8))
+            .push_option(None::<u64>)
+            .push_bool(true)
+            .push_char('é')
+            .push((Be(7u16), Varint(300u32)))
+            .push(map.clone())
+            .push(IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .push_raw(b"rest")
+            .build();
+
+        type Fields<'a> = (u32, &'a str, Vec<u16>, Option<i8>, Option<u64>, bool, char, (Be<u16>, Varint<u32>), HashMap<u8, char>, IpAddr, RawBody);
+        let mut payload = Payload::from_bytes(&bytes);
+        let fields = <Fields as FromPayload>::from(&mut payload).unwrap();
+
+        assert_eq!(fields, (
+            10, "name", vec![1, 2, 3], Some(-4), None, true, 'é',
+            (Be(7), Varint(300)), map,
+            IpAddr::V4(Ipv4Addr::LOCALHOST), RawBody(b"rest".to_vec()),
+        ));
+        assert!(payload.is_empty());
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn count_overflow_panics() {
+        let items = vec![(); u32::MAX as usize + 1];
+        assert!(std::panic::catch_unwind(|| PayloadBuilder::new().push_vec(&items)).is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+pub(crate) struct ThreadPool {
+    sender: Sender<Job>,
+}
+
+impl ThreadPool {
+    pub(crate) fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..size.max(1) {
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                let job = receiver.lock().unwrap().recv();
+                match job {
+                    // A panicking job must not take the worker down with it.
+                    Ok(job) => { let _ = panic::catch_unwind(AssertUnwindSafe(job)); }
+                    Err(_) => break,
+                }
+            });
+        }
+
+        ThreadPool { sender }
+    }
+
+    pub(crate) fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        let _ = self.sender.send(Box::new(job));
+    }
+}
@@ -1,42 +1,660 @@
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::time::{Duration, Instant};
 
-#[derive(Clone, Copy)]
+use crate::payload::PayloadBuilder;
+use crate::service::{self, ByteOrder, Payload, PayloadError, ToPayload};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub enum RequestType {
     Get,
     Post,
+    Put,
+    Delete,
+    Patch,
+}
+
+impl RequestType {
+    pub const ALL: [RequestType; 5] = [
+        RequestType::Get,
+        RequestType::Post,
+        RequestType::Put,
+        RequestType::Delete,
+        RequestType::Patch,
+    ];
+
+    /// The byte identifying the method in request frames. The mapping is
+    /// part of the wire format and stays fixed: `Get` is 0, `Post` 1, `Put`
+    /// 2, `Delete` 3 and `Patch` 4.
+    pub fn to_byte(self) -> u8 {
+        match self {
+            RequestType::Get => 0,
+            RequestType::Post => 1,
+            RequestType::Put => 2,
+            RequestType::Delete => 3,
+            RequestType::Patch => 4,
+        }
+    }
+
+    /// The inverse of `to_byte`, `None` for bytes no method maps to.
+    pub fn from_byte(b: u8) -> Option<RequestType> {
+        RequestType::ALL.into_iter().find(|method| method.to_byte() == b)
+    }
+}
+
+impl Display for RequestType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RequestType::Get => "get",
+            RequestType::Post => "post",
+            RequestType::Put => "put",
+            RequestType::Delete => "delete",
+            RequestType::Patch => "patch",
+        };
+        write!(f, "{name}")
+    }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Request {
     pub(crate) ty: RequestType,
     pub(crate) path: Path,
+    pub(crate) query: Query,
     pub(crate) payload: Vec<u8>,
 }
 
 impl Request {
+    /// Splits off the query and percent-decodes the path. A path with a
+    /// malformed `%` escape is kept as it is; `try_new` rejects it instead.
+    pub fn new(ty: RequestType, path: impl Into<Path>, payload: Vec<u8>) -> Request {
+        let (path, query) = path.into().split_query();
+        let path = decode_path(path.as_str()).map(Path::from).unwrap_or(path);
+        Request { ty, path, query, payload }
+    }
+
+    /// Like `new`, but fails with `InvalidPercentEncoding` on a malformed
+    /// escape, or `InvalidUtf8` if the decoded path isn't UTF-8.
+    pub fn try_new(ty: RequestType, path: impl Into<Path>, payload: Vec<u8>) -> Result<Request, PayloadError> {
+        let (path, query) = path.into().split_query();
+        let path = Path::from(decode_path(path.as_str())?);
+        Ok(Request { ty, path, query, payload })
+    }
+
+    pub fn method(&self) -> RequestType {
+        self.ty
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
     pub fn get(path: impl Into<Path>, payload: Vec<u8>) -> Request {
-        Request { ty: RequestType::Get, path: path.into(), payload }
+        Request::new(RequestType::Get, path, payload)
     }
 
     pub fn post(path: impl Into<Path>, payload: Vec<u8>) -> Request {
-        Request { ty: RequestType::Post, path: path.into(), payload }
+        Request::new(RequestType::Post, path, payload)
+    }
+
+    pub fn put(path: impl Into<Path>, payload: Vec<u8>) -> Request {
+        Request::new(RequestType::Put, path, payload)
+    }
+
+    pub fn delete(path: impl Into<Path>, payload: Vec<u8>) -> Request {
+        Request::new(RequestType::Delete, path, payload)
+    }
+
+    pub fn patch(path: impl Into<Path>, payload: Vec<u8>) -> Request {
+        Request::new(RequestType::Patch, path, payload)
+    }
+
+    pub fn builder() -> RequestBuilder {
+        RequestBuilder::default()
+    }
+}
+
+/// Builds a `Request` with its payload assembled field by field, in the
+/// order the handler takes its arguments.
+#[derive(Debug, Clone, Default)]
+pub struct RequestBuilder {
+    method: Option<RequestType>,
+    path: Option<Path>,
+    payload: PayloadBuilder,
+}
+
+impl RequestBuilder {
+    pub fn method(mut self, method: RequestType) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    /// The path may include a query string, as with `Request::new`.
+    pub fn path(mut self, path: impl Into<Path>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn field(mut self, field: impl ToPayload) -> Self {
+        self.payload = self.payload.push(field);
+        self
+    }
+
+    /// Fails with `MissingRequest` if the method or the path wasn't set.
+    pub fn build(self) -> Result<Request, PayloadError> {
+        match (self.method, self.path) {
+            (Some(method), Some(path)) => Ok(Request::new(method, path, self.payload.build())),
+            _ => Err(PayloadError::MissingRequest),
+        }
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+/// A request frame is laid out as
+///
+///   method: u8 | path_len: u16 | path: [u8; path_len] | payload_len: u32 | payload: [u8; payload_len]
+///
+/// where the lengths are big-endian and the path is UTF-8, including the
+/// query string if there is one. `%` and `?` in the path are percent-encoded.
+/// A path of more than `u16::MAX` bytes once encoded, or a payload of more
+/// than `u32::MAX` bytes, fails with `TooLarge`.
+pub fn encode(request: &Request) -> Result<Vec<u8>, PayloadError> {
+    let mut path = request.path.as_str().replace('%', "%25").replace('?', "%3F");
+    if !request.query.as_str().is_empty() {
+        path.push('?');
+        path.push_str(request.query.as_str());
+    }
+
+    let too_large = |len, max| PayloadError::TooLarge { len, max };
+    let path_len = u16::try_from(path.len()).map_err(|_| too_large(path.len(), u16::MAX as usize))?;
+    let payload_len = u32::try_from(request.payload.len()).map_err(|_| too_large(request.payload.len(), u32::MAX as usize))?;
+
+    let mut frame = Vec::with_capacity(1 + 2 + path.len() + 4 + request.payload.len());
+    frame.push(request.ty.to_byte());
+    frame.extend_from_slice(&path_len.to_be_bytes());
+    frame.extend_from_slice(path.as_bytes());
+    frame.extend_from_slice(&payload_len.to_be_bytes());
+    frame.extend_from_slice(&request.payload);
+    Ok(frame)
+}
+
+/// Decodes one frame laid out as described on `encode`. Bytes after the frame
+/// are ignored.
+pub fn decode(bytes: &[u8]) -> Result<Request, PayloadError> {
+    decode_frame(bytes).map(|(request, _)| request)
+}
+
+/// Like `encode`, followed by a big-endian CRC-32 (IEEE) of the frame.
+pub fn encode_with_checksum(request: &Request) -> Result<Vec<u8>, PayloadError> {
+    let mut frame = encode(request)?;
+    let checksum = crc32(&frame);
+    frame.extend_from_slice(&checksum.to_be_bytes());
+    Ok(frame)
+}
+
+/// Decodes a frame written by `encode_with_checksum`, failing with
+/// `ChecksumMismatch` if the frame doesn't match its trailer.
+pub fn decode_with_checksum(bytes: &[u8]) -> Result<Request, PayloadError> {
+    let (request, len) = decode_frame(bytes)?;
+    let mut trailer = Payload::from_bytes(&bytes[len..]).with_byte_order(ByteOrder::BigEndian);
+    let expected = <u32 as service::FromPayload>::from(&mut trailer)?;
+
+    let actual = crc32(&bytes[..len]);
+    if actual != expected {
+        return Err(PayloadError::ChecksumMismatch { expected, actual });
+    }
+    Ok(request)
+}
+
+// Returns the request along with the length of its frame.
+fn decode_frame(bytes: &[u8]) -> Result<(Request, usize), PayloadError> {
+    let mut frame = Payload::from_bytes(bytes).with_byte_order(ByteOrder::BigEndian);
+
+    let method = <u8 as service::FromPayload>::from(&mut frame)?;
+    let method = RequestType::from_byte(method).ok_or(PayloadError::InvalidDiscriminant(method.into()))?;
+
+    let path_len = <u16 as service::FromPayload>::from(&mut frame)?;
+    let path = std::str::from_utf8(frame.take(path_len as usize)?).map_err(|_| PayloadError::InvalidUtf8)?;
+
+    let payload_len = <u32 as service::FromPayload>::from(&mut frame)?;
+    let payload = frame.take(payload_len as usize)?.to_vec();
+
+    let len = bytes.len() - frame.remaining();
+    Ok((Request::try_new(method, path, payload)?, len))
+}
+
+// CRC-32 with the reflected IEEE polynomial, as used by zlib and Ethernet.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Handler argument holding the method of the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Method(pub RequestType);
+
+/// Handler argument holding the request path including its query string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FullPath(pub String);
+
+/// Handler argument holding the pattern of the route that matched, like
+/// `/book/:id`, and the params it captured. Handlers reached through the
+/// fallback have no matched route and fail to extract it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedPath {
+    pub pattern: Path,
+    pub params: Params,
+}
+
+/// Handler argument holding the whole request, with every payload byte
+/// including those read by earlier arguments. It consumes the rest of the
+/// payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Raw(pub Request);
+
+/// Handler argument holding the instant by which the handler should be done,
+/// set when the server has a `handler_timeout`, so long handlers can give up
+/// early. `None` without a timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline(pub Option<Instant>);
+
+impl Deadline {
+    /// Time left until the deadline, zero once it has passed.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.0.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.0.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Handler argument holding the ID the server assigned the request, unique
+/// among the requests it handles and increasing from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RequestId(pub u64);
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct Path {
     p: String,
 }
 
+impl Path {
+    pub fn as_str(&self) -> &str {
+        &self.p
+    }
+
+    fn split_query(self) -> (Path, Query) {
+        match self.p.split_once('?') {
+            Some((path, query)) => (Path::from(path), Query::parse(query)),
+            None => (self, Query::default()),
+        }
+    }
+
+    // Joins `prefix` and `self` with exactly one `/` between them, so that
+    // `/api/` + `/book` and `/api` + `book` both give `/api/book`.
+    pub(crate) fn prefixed(&self, prefix: &Path) -> Path {
+        let prefix = prefix.p.trim_end_matches('/');
+        let path = self.p.trim_start_matches('/');
+
+        match (prefix.is_empty(), path.is_empty()) {
+            (true, true) => Path::from("/"),
+            (true, false) => Path::from(format!("/{path}")),
+            (false, true) if prefix.starts_with('/') => Path::from(prefix),
+            (false, true) => Path::from(format!("/{prefix}")),
+            (false, false) if prefix.starts_with('/') => Path::from(format!("{prefix}/{path}")),
+            (false, false) => Path::from(format!("/{prefix}/{path}")),
+        }
+    }
+
+    pub(crate) fn segments(&self) -> std::str::Split<'_, char> {
+        self.p.split('/')
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        self.segments().any(|s| s.starts_with(':') || s.starts_with('*'))
+    }
+
+    // `/book` for `/book/` and the other way around. `None` for the root.
+    pub(crate) fn toggled_trailing_slash(&self) -> Option<Path> {
+        match self.p.as_str() {
+            "" | "/" => None,
+            p if p.ends_with('/') => Some(Path::from(&p[..p.len() - 1])),
+            p => Some(Path::from(format!("{p}/"))),
+        }
+    }
+
+    // Lowercases the static segments, leaving `:param` and `*wildcard` names
+    // as they are.
+    pub(crate) fn lowercased(&self) -> Path {
+        let segments: Vec<String> = self
+            .segments()
+            .map(|s| match s.starts_with(':') || s.starts_with('*') {
+                true => s.to_string(),
+                false => s.to_lowercase(),
+            })
+            .collect();
+        Path::from(segments.join("/"))
+    }
+
+    // Matches `path` against `self` used as a route pattern. Segments starting
+    // with `:` capture the corresponding segment of `path`, and a trailing
+    // segment starting with `*` captures all remaining segments. With
+    // `ignore_case` the static segments of `self` must already be lowercase.
+    pub(crate) fn captures(&self, path: &Path, ignore_case: bool) -> Option<Params> {
+        let mut params = Params::default();
+        let mut pattern = self.segments();
+        let mut segments = path.segments();
+
+        loop {
+            match (pattern.next(), segments.next()) {
+                (None, None) => return Some(params),
+                (Some(p), Some(s)) => {
+                    if let Some(name) = p.strip_prefix('*') {
+                        let rest: Vec<&str> = std::iter::once(s).chain(segments).collect();
+                        params.values.insert(name.to_string(), rest.join("/"));
+                        return Some(params);
+                    }
+
+                    match p.strip_prefix(':') {
+                        Some(name) => { params.values.insert(name.to_string(), s.to_string()); }
+                        None if p == s => {}
+                        None if ignore_case && p == s.to_lowercase() => {}
+                        None => return None,
+                    }
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    values: HashMap<String, String>,
+}
+
+/// Query parameters in the order they appear in the request. Repeated keys
+/// are all kept: `get` returns the first value and `get_all` every value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    raw: String,
+    pairs: Vec<(String, String)>,
+}
+
+impl Query {
+    fn parse(query: &str) -> Query {
+        let pairs = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(key), percent_decode(value))
+            })
+            .collect();
+
+        Query { raw: query.to_string(), pairs }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs.iter().filter(move |(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+// Unlike in queries, `+` is kept as is and a `%` must start a valid escape.
+fn decode_path(s: &str) -> Result<String, PayloadError> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            out.push(hex_escape(s, i).ok_or(PayloadError::InvalidPercentEncoding)?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(out).map_err(|_| PayloadError::InvalidUtf8)
+}
+
+// The byte of the `%XX` escape starting at `i`, if both `X` are hex digits.
+// `from_str_radix` alone would also take a sign, like in `%+1`.
+fn hex_escape(s: &str, i: usize) -> Option<u8> {
+    let hex = s.get(i + 1..i + 3)?;
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+// Decodes `%XX` escapes and `+` as a space. Malformed escapes are kept as is.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => match hex_escape(s, i) {
+                Some(b) => {
+                    out.push(b);
+                    i += 3;
+                    continue;
+                }
+                None => out.push(b'%'),
+            },
+            b'+' => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
 impl Display for Path {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "{}", self.p)
+        write!(f, "{}", self.p)
     }
 }
 
+/// An empty or whitespace-only path is the root `/`, for route patterns and
+/// request paths alike.
 impl<T> From<T> for Path 
     where T: Into<String>
 {
     fn from(value: T) -> Self {
-        Path { p: value.into() }
+        let p = value.into();
+        match p.trim().is_empty() {
+            true => Path { p: "/".to_string() },
+            false => Path { p },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixed_path() {
+        let join = |prefix: &str, path: &str| Path::from(path).prefixed(&Path::from(prefix));
+
+        assert_eq!(join("/api", "/book"), Path::from("/api/book"));
+        assert_eq!(join("/api/", "book"), Path::from("/api/book"));
+        assert_eq!(join("api/", "/book"), Path::from("/api/book"));
+        assert_eq!(join("/api", "/"), Path::from("/api"));
+        assert_eq!(join("/", "/book"), Path::from("/book"));
+        assert_eq!(join("", ""), Path::from("/"));
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let request = Request::put("/book?author=rust", vec![1, 2, 3]);
+        let frame = encode(&request).unwrap();
+        let decoded = decode(&frame).unwrap();
+
+        assert_eq!(decoded.ty, RequestType::Put);
+        assert_eq!(decoded.path, Path::from("/book"));
+        assert_eq!(decoded.query.get("author"), Some("rust"));
+        assert_eq!(decoded.payload, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn frame_length_limits() {
+        let longest = format!("/{}", "a".repeat(u16::MAX as usize - 1));
+        let request = Request::get(longest.as_str(), vec![1]);
+        assert_eq!(decode(&encode(&request).unwrap()), Ok(request));
+
+        let too_long = Request::get(format!("{longest}a").as_str(), vec![]);
+        assert_eq!(encode(&too_long), Err(PayloadError::TooLarge { len: u16::MAX as usize + 1, max: u16::MAX as usize }));
+
+        // Escaping counts towards the limit.
+        let escaped = Request::get(format!("/%25{}", "a".repeat(u16::MAX as usize - 3)).as_str(), vec![]);
+        assert_eq!(escaped.path.as_str().len(), u16::MAX as usize - 1);
+        assert!(matches!(encode(&escaped), Err(PayloadError::TooLarge { .. })));
+    }
+
+    #[test]
+    fn frame_lengths_exceed_buffer() {
+        let frame = encode(&Request::get("/book", vec![1, 2, 3])).unwrap();
+
+        assert_eq!(
+            decode(&frame[..frame.len() - 1]),
+            Err(PayloadError::UnexpectedEof { needed: 3, remaining: 2 })
+        );
+        assert!(decode(&frame[..4]).is_err());
+        assert_eq!(decode(&[9]), Err(PayloadError::InvalidDiscriminant(9)));
+    }
+
+    #[test]
+    fn method_bytes() {
+        let bytes: Vec<u8> = RequestType::ALL.iter().map(|method| method.to_byte()).collect();
+        assert_eq!(bytes, [0, 1, 2, 3, 4]);
+        for method in RequestType::ALL {
+            assert_eq!(RequestType::from_byte(method.to_byte()), Some(method));
+        }
+        assert_eq!(RequestType::from_byte(5), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn checksummed_frame() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let request = Request::post("/book", vec![1, 2, 3]);
+        let mut frame = encode_with_checksum(&request).unwrap();
+        assert_eq!(decode_with_checksum(&frame), Ok(request));
+
+        let last = frame.len() - 5;
+        frame[last] ^= 0x01;
+        assert!(matches!(decode_with_checksum(&frame), Err(PayloadError::ChecksumMismatch { .. })));
+        assert!(matches!(decode_with_checksum(&frame[..frame.len() - 1]), Err(PayloadError::UnexpectedEof { .. })));
+    }
+
+    #[test]
+    fn query_string() {
+        let request = Request::get("/book?author=rust%20lang&year=2023&tag=a&tag=b+c&flag", vec![]);
+
+        assert_eq!(request.path, Path::from("/book"));
+        assert_eq!(request.query().get("author"), Some("rust lang"));
+        assert_eq!(request.query().get("year"), Some("2023"));
+        assert_eq!(request.query().get_all("tag").collect::<Vec<_>>(), vec!["a", "b c"]);
+        assert_eq!(request.query().get("flag"), Some(""));
+        assert_eq!(request.query().get("missing"), None);
+
+        let request = Request::get("/book?n=%+1", vec![]);
+        assert_eq!(request.query().get("n"), Some("% 1"));
+    }
+
+    #[test]
+    fn request_builder() {
+        let request = Request::builder().method(RequestType::Post).path("/book?draft").field(10u32).field("name").build();
+        let payload = [10u32.to_payload(), "name".to_payload()].concat();
+        assert_eq!(request, Ok(Request::post("/book?draft", payload)));
+
+        assert_eq!(Request::builder().path("/book").build(), Err(PayloadError::MissingRequest));
+        assert_eq!(Request::builder().method(RequestType::Get).build(), Err(PayloadError::MissingRequest));
+    }
+
+    #[test]
+    fn empty_path_is_root() {
+        for path in ["", "/", "  ", "\t"] {
+            assert_eq!(Path::from(path).as_str(), "/");
+        }
+        assert_eq!(Request::get("?x=1", vec![]).path, Path::from("/"));
+        assert_eq!(Request::get("%20", vec![]).path, Path::from("/"));
+        assert_eq!(Path::from(" /a ").as_str(), " /a ");
+    }
+
+    #[test]
+    fn path_display() {
+        assert_eq!(format!("{}", Path::from("/x")), "/x");
+    }
+
+    #[test]
+    fn percent_decoded_path() {
+        assert_eq!(Request::get("/my%20book", vec![]).path, Path::from("/my book"));
+        assert_eq!(Request::get("/book%2Fextra?q=%2F", vec![]).path, Path::from("/book/extra"));
+        assert_eq!(Request::get("/a+b", vec![]).path, Path::from("/a+b"));
+
+        assert_eq!(Request::try_new(RequestType::Get, "/book%2", vec![]), Err(PayloadError::InvalidPercentEncoding));
+        assert_eq!(Request::try_new(RequestType::Get, "/book%zz", vec![]), Err(PayloadError::InvalidPercentEncoding));
+        assert_eq!(Request::try_new(RequestType::Get, "/a%+1", vec![]), Err(PayloadError::InvalidPercentEncoding));
+        assert_eq!(Request::try_new(RequestType::Get, "/a%ff", vec![]), Err(PayloadError::InvalidUtf8));
+
+        assert_eq!(Request::get("/100%", vec![]).path, Path::from("/100%"));
+        assert_eq!(Request::get("/a%+1", vec![]).path, Path::from("/a%+1"));
+        assert_eq!(Request::get("/a%ff", vec![]).path, Path::from("/a%ff"));
+
+        let request = Request::get("/100%25?done", vec![]);
+        assert_eq!(request.path, Path::from("/100%"));
+        assert_eq!(decode(&encode(&request).unwrap()), Ok(request));
+    }
+}
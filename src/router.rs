@@ -0,0 +1,313 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::Split;
+
+use crate::request::{Params, Path};
+
+/// Route table matching static, `:param` and `*wildcard` patterns.
+///
+/// Static patterns are looked up directly in a map, as they always win over
+/// dynamic ones. Dynamic patterns are stored in a tree with one level per
+/// path segment. Each node has static children keyed by segment, one child
+/// for `:param` segments and one for a trailing `*wildcard`.
+///
+/// Tree lookups try static segments first, then params, then wildcards, so the
+/// most specific route wins, compared from the first segment on. Patterns of
+/// the same shape, like `/book/:id` and `/book/:name`, end on the same node,
+/// where the lexicographically smallest one wins.
+///
+/// `ambiguous_with` finds the registrations that precedence settles only
+/// arbitrarily: a `:param` and a `*wildcard` at the same position, like
+/// `/a/:x` and `/a/*rest`, two wildcards at the same position, or two patterns
+/// of the same shape.
+#[derive(Clone)]
+pub struct Router<T> {
+    exact: HashMap<Path, T>,
+    root: Node<T>,
+    len: usize,
+}
+
+#[derive(Clone)]
+struct Node<T> {
+    routes: BTreeMap<Path, T>,
+    statics: HashMap<String, Node<T>>,
+    param: Option<Box<Node<T>>>,
+    wildcard: Option<Box<Node<T>>>,
+}
+
+impl<T> Default for Router<T> {
+    fn default() -> Self {
+        Router { exact: HashMap::new(), root: Node::default(), len: 0 }
+    }
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node { routes: BTreeMap::new(), statics: HashMap::new(), param: None, wildcard: None }
+    }
+}
+
+impl<T> Router<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the value previously stored for exactly `pattern`.
+    pub fn insert(&mut self, pattern: Path, value: T) -> Option<T> {
+        if !pattern.is_dynamic() {
+            let previous = self.exact.insert(pattern, value);
+            self.len += previous.is_none() as usize;
+            return previous;
+        }
+
+        let mut node = &mut self.root;
+        for segment in pattern_segments(&pattern) {
+            node = match segment.chars().next() {
+                Some('*') => node.wildcard.get_or_insert_with(Box::default),
+                Some(':') => node.param.get_or_insert_with(Box::default),
+                _ => node.statics.entry(segment.to_string()).or_default(),
+            };
+        }
+
+        let previous = node.routes.insert(pattern, value);
+        self.len += previous.is_none() as usize;
+        previous
+    }
+
+    /// Removes exactly `pattern`, returning its value.
+    pub fn remove(&mut self, pattern: &Path) -> Option<T> {
+        let removed = match pattern.is_dynamic() {
+            false => self.exact.remove(pattern),
+            true => {
+                let mut node = &mut self.root;
+                for segment in pattern_segments(pattern) {
+                    let next = match segment.chars().next() {
+                        Some('*') => node.wildcard.as_deref_mut(),
+                        Some(':') => node.param.as_deref_mut(),
+                        _ => node.statics.get_mut(segment),
+                    };
+                    node = next?;
+                }
+                node.routes.remove(pattern)
+            }
+        };
+        self.len -= removed.is_some() as usize;
+        removed
+    }
+
+    /// Whether exactly `pattern` is registered, without matching it against
+    /// other patterns.
+    pub fn contains_key(&self, pattern: &Path) -> bool {
+        if !pattern.is_dynamic() {
+            return self.exact.contains_key(pattern);
+        }
+
+        let mut node = &self.root;
+        for segment in pattern_segments(pattern) {
+            let next = match segment.chars().next() {
+                Some('*') => node.wildcard.as_deref(),
+                Some(':') => node.param.as_deref(),
+                _ => node.statics.get(segment),
+            };
+            match next {
+                Some(next) => node = next,
+                None => return false,
+            }
+        }
+        node.routes.contains_key(pattern)
+    }
+
+    /// A registered pattern, other than `pattern` itself, that matches the
+    /// same paths as `pattern` with no static segment telling them apart.
+    pub fn ambiguous_with(&self, pattern: &Path) -> Option<&Path> {
+        if !pattern.is_dynamic() {
+            return None;
+        }
+        self.keys().find(|other| *other != pattern && ambiguous(pattern, other))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Path> {
+        self.entries().map(|(pattern, _)| pattern)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&Path, &T)> {
+        let mut stack = vec![&self.root];
+        let mut routes: Vec<_> = self.exact.iter().collect();
+        while let Some(node) = stack.pop() {
+            routes.extend(node.routes.iter());
+            stack.extend(node.statics.values());
+            stack.extend(node.param.as_deref());
+            stack.extend(node.wildcard.as_deref());
+        }
+        routes.into_iter()
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = (Path, T)> {
+        let mut stack = vec![std::mem::take(&mut self.root)];
+        let mut routes: Vec<_> = self.exact.drain().collect();
+        while let Some(node) = stack.pop() {
+            routes.extend(node.routes);
+            stack.extend(node.statics.into_values());
+            stack.extend(node.param.map(|n| *n));
+            stack.extend(node.wildcard.map(|n| *n));
+        }
+        self.len = 0;
+        routes.into_iter()
+    }
+
+    /// Finds the route matching `path` with the params it captures. With
+    /// `ignore_case` the static segments of the patterns must be lowercase.
+    pub fn find(&self, path: &Path, ignore_case: bool) -> Option<(&Path, &T, Params)> {
+        let exact = match ignore_case {
+            true => self.exact.get_key_value(&path.lowercased()),
+            false => self.exact.get_key_value(path),
+        };
+        if let Some((pattern, value)) = exact {
+            return Some((pattern, value, Params::default()));
+        }
+
+        let (pattern, value) = self.root.find(path.segments(), ignore_case)?;
+        Some((pattern, value, pattern.captures(path, ignore_case)?))
+    }
+}
+
+// Segments of `pattern` up to and including a `*wildcard`, which captures
+// everything after it.
+fn pattern_segments(pattern: &Path) -> impl Iterator<Item = &str> {
+    let mut done = false;
+    pattern.segments().take_while(move |segment| {
+        let take = !done;
+        done = done || segment.starts_with('*');
+        take
+    })
+}
+
+// Compares segments from the first one on. A static segment against a
+// different static or a dynamic one is resolved by precedence, so only
+// dynamic segments all the way to the end, or up to a wildcard, make two
+// patterns ambiguous. A param against a wildcard only is if nothing follows
+// the param: segments after it already decide which pattern a path takes.
+fn ambiguous(a: &Path, b: &Path) -> bool {
+    let (mut a, mut b) = (pattern_segments(a).peekable(), pattern_segments(b).peekable());
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return true,
+            (Some(x), Some(y)) => match (x.chars().next(), y.chars().next()) {
+                (Some('*'), Some('*')) => return true,
+                (Some('*'), Some(':')) => return b.peek().is_none(),
+                (Some(':'), Some('*')) => return a.peek().is_none(),
+                (Some(':'), Some(':')) => {}
+                (Some(':'), _) | (_, Some(':')) | (Some('*'), _) | (_, Some('*')) => return false,
+                _ if x != y => return false,
+                _ => {}
+            },
+            _ => return false,
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn find(&self, mut segments: Split<'_, char>, ignore_case: bool) -> Option<(&Path, &T)> {
+        let Some(segment) = segments.next() else {
+            return self.routes.iter().next();
+        };
+
+        let child = match ignore_case {
+            true => self.statics.get(&segment.to_lowercase()),
+            false => self.statics.get(segment),
+        };
+        child
+            .and_then(|node| node.find(segments.clone(), ignore_case))
+            .or_else(|| self.param.as_ref().and_then(|node| node.find(segments, ignore_case)))
+            .or_else(|| self.wildcard.as_ref().and_then(|node| node.routes.iter().next()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(patterns: &[&str]) -> Router<usize> {
+        let mut router = Router::new();
+        for (i, pattern) in patterns.iter().enumerate() {
+            router.insert(Path::from(*pattern), i);
+        }
+        router
+    }
+
+    fn matched(router: &Router<usize>, path: &str) -> Option<usize> {
+        router.find(&Path::from(path), false).map(|(_, i, _)| *i)
+    }
+
+    #[test]
+    fn most_specific_route_wins() {
+        let router = router(&["/book/:id", "/book/latest", "/book/*rest", "/:any/latest/x", "/book/:id/x"]);
+
+        assert_eq!(matched(&router, "/book/latest"), Some(1));
+        assert_eq!(matched(&router, "/book/7"), Some(0));
+        assert_eq!(matched(&router, "/book/7/x"), Some(4));
+        assert_eq!(matched(&router, "/book/latest/x"), Some(4));
+        assert_eq!(matched(&router, "/book/7/y"), Some(2));
+        assert_eq!(matched(&router, "/shelf/latest/x"), Some(3));
+        assert_eq!(matched(&router, "/book"), None);
+    }
+
+    #[test]
+    fn captures_params() {
+        let router = router(&["/book/:id/chapter/:chapter", "/files/*path"]);
+
+        let (_, _, params) = router.find(&Path::from("/book/7/chapter/2"), false).unwrap();
+        assert_eq!((params.get("id"), params.get("chapter")), (Some("7"), Some("2")));
+
+        let (_, _, params) = router.find(&Path::from("/files/a/b.txt"), false).unwrap();
+        assert_eq!(params.get("path"), Some("a/b.txt"));
+    }
+
+    #[test]
+    fn ambiguous_patterns() {
+        let router = router(&["/a/:x", "/b/:x/c", "/c/*rest", "/d/e"]);
+        let ambiguous = |pattern: &str| router.ambiguous_with(&Path::from(pattern)).map(Path::as_str);
+
+        assert_eq!(ambiguous("/a/*rest"), Some("/a/:x"));
+        assert_eq!(ambiguous("/a/:y"), Some("/a/:x"));
+        assert_eq!(ambiguous("/b/:y/*rest"), None);
+        assert_eq!(ambiguous("/c/:x"), Some("/c/*rest"));
+        assert_eq!(ambiguous("/c/*other"), Some("/c/*rest"));
+
+        assert_eq!(ambiguous("/a/:x"), None);
+        assert_eq!(ambiguous("/a/latest"), None);
+        assert_eq!(ambiguous("/a/:x/y"), None);
+        assert_eq!(ambiguous("/b/:x/d"), None);
+        assert_eq!(ambiguous("/b/*rest"), None);
+        assert_eq!(ambiguous("/c/:x/y"), None);
+        assert_eq!(ambiguous("/:x/e"), None);
+    }
+
+    #[test]
+    fn insert_and_drain() {
+        let mut router = router(&["/a", "/b/:id"]);
+        assert_eq!(router.insert(Path::from("/a"), 9), Some(0));
+        assert_eq!(router.len(), 2);
+        assert!(router.contains_key(&Path::from("/b/:id")));
+        assert!(!router.contains_key(&Path::from("/b/1")));
+
+        assert_eq!(router.remove(&Path::from("/b/:other")), None);
+        assert_eq!(router.remove(&Path::from("/b/:id")), Some(1));
+        assert_eq!(matched(&router, "/b/1"), None);
+        assert_eq!(router.len(), 1);
+        router.insert(Path::from("/b/:id"), 1);
+
+        let mut drained: Vec<_> = router.drain().collect();
+        drained.sort();
+        assert_eq!(drained, vec![(Path::from("/a"), 9), (Path::from("/b/:id"), 1)]);
+        assert!(router.is_empty());
+        assert_eq!(matched(&router, "/a"), None);
+    }
+}
@@ -1,13 +1,199 @@
-use std::collections::HashMap;
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::ops::Deref;
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::request::{Request, RequestType, Path};
-use crate::service::{BoxedService, HttpStatus, FromPayload, Payload};
+use crate::request::{self, Request, RequestId, RequestType, Params, Path};
+use crate::service::{self, Body, BoxedService, ByteOrder, DEFAULT_MAX_PAYLOAD_LEN, Dependencies, HttpStatus, Payload, PayloadError, RawBody, Responder, ServiceResponse, ToPayload, UnreadHook};
 use crate::handler::{Factory, Handler};
+use crate::metrics::{Metrics, Recorder};
+use crate::middleware::Middleware;
+use crate::pool::ThreadPool;
+use crate::router::Router;
 
-#[derive(Default)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    Conflict { method: RequestType, path: Path },
+    /// `path` would match the same requests as `existing`, only told apart
+    /// by which kind of segment has precedence. See `Router`.
+    Ambiguous { method: RequestType, path: Path, existing: Path },
+    /// `path` has an empty segment or an unnamed param or wildcard.
+    EmptySegment { method: RequestType, path: Path },
+    /// `path` has a wildcard before its last segment, which would leave the
+    /// segments after it unmatched.
+    MisplacedWildcard { method: RequestType, path: Path },
+    /// The handler takes an `Inject<T>` for a `T` named `ty` that was never
+    /// given to `Server::inject`.
+    MissingDependency { method: RequestType, path: Path, ty: &'static str },
+}
+
+impl Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteError::Conflict { method, path } => write!(f, "{method} handler for path {path} is already registered"),
+            RouteError::Ambiguous { method, path, existing } => write!(f, "{method} handler for path {path} is ambiguous with {existing}"),
+            RouteError::EmptySegment { method, path } => write!(f, "{method} handler for path {path} has an empty segment"),
+            RouteError::MisplacedWildcard { method, path } => write!(f, "{method} handler for path {path} has a wildcard before its last segment"),
+            RouteError::MissingDependency { method, path, ty } => write!(f, "{method} handler for path {path} injects {ty}, which the server wasn't given"),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+// Segments between the leading and a trailing slash must be non-empty, and
+// params and wildcards must be named.
+fn has_empty_segment(path: &Path) -> bool {
+    let p = path.as_str();
+    let inner = p.strip_prefix('/').unwrap_or(p);
+    let inner = inner.strip_suffix('/').unwrap_or(inner);
+    !inner.is_empty() && inner.split('/').any(|segment| matches!(segment, "" | ":" | "*"))
+}
+
+// A wildcard takes the rest of the path, so only the last segment may be one.
+fn has_misplaced_wildcard(path: &Path) -> bool {
+    let p = path.as_str();
+    let inner = p.strip_suffix('/').unwrap_or(p);
+    let mut segments = inner.split('/').rev().skip(1);
+    segments.any(|segment| segment.starts_with('*'))
+}
+
+/// A server whose routes passed `Server::finalize`, the only way to serve
+/// one. It derefs to `Server` for everything else.
+#[derive(Clone)]
+pub struct FinalizedServer(Server);
+
+impl FinalizedServer {
+    pub fn serve(self, addr: &str) -> io::Result<()> {
+        self.0.serve(addr)
+    }
+
+    /// Every connection gets its own thread for I/O, while the decoded
+    /// requests run on a fixed pool of `workers` threads.
+    pub fn serve_listener(self, listener: TcpListener) -> io::Result<()> {
+        self.0.serve_listener(listener)
+    }
+
+    /// Serves like `serve_listener` until `shutdown` is triggered. Then no new
+    /// connections are accepted, requests already being handled are answered,
+    /// idle connections are closed, and this returns once all connections are
+    /// done.
+    pub fn serve_until(self, listener: TcpListener, shutdown: Shutdown) -> io::Result<()> {
+        self.0.serve_until(listener, shutdown)
+    }
+
+    pub fn into_inner(self) -> Server {
+        self.0
+    }
+}
+
+impl Deref for FinalizedServer {
+    type Target = Server;
+
+    fn deref(&self) -> &Server {
+        &self.0
+    }
+}
+
+/// How a request path that differs from a route only by a trailing slash is
+/// handled. The root path `/` is never changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// `/book` and `/book/` are different paths.
+    #[default]
+    Strict,
+    /// The request is handled by the route with or without the slash.
+    Ignore,
+    /// The request is answered `PermanentRedirect` with the path of the
+    /// matching route as body.
+    RedirectToCanonical,
+}
+
+/// What the `Server::on_complete` hook is told about each request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestInfo<'a> {
+    pub id: RequestId,
+    pub method: RequestType,
+    pub path: &'a Path,
+    pub status: HttpStatus,
+    /// Time spent in middleware and dispatch, including the handler.
+    pub elapsed: Duration,
+}
+
+type CompletionHook = Arc<dyn Fn(&RequestInfo) + Send + Sync>;
+
+/// Clones share their services, middleware, state, metrics and request IDs.
+#[derive(Clone)]
 pub struct Server {
-    get: HashMap<Path, BoxedService>,
-    post: HashMap<Path, BoxedService>,
+    get: Router<BoxedService>,
+    post: Router<BoxedService>,
+    put: Router<BoxedService>,
+    delete: Router<BoxedService>,
+    patch: Router<BoxedService>,
+    byte_order: ByteOrder,
+    fallback: Option<BoxedService>,
+    workers: usize,
+    max_payload_len: usize,
+    route_limits: HashMap<Path, usize>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    state: Option<Arc<dyn Any + Send + Sync>>,
+    dependencies: Arc<Dependencies>,
+    strict_payload: bool,
+    metrics: Arc<Recorder>,
+    next_id: Arc<AtomicU64>,
+    handler_timeout: Option<Duration>,
+    case_insensitive: bool,
+    trailing_slash: TrailingSlash,
+    verify_checksum: bool,
+    on_complete: Option<CompletionHook>,
+    on_unread_payload: Option<UnreadHook>,
+    // Registrations that replaced an existing route, reported by `finalize`.
+    duplicates: Vec<RouteError>,
+    // Routes the server registered itself, like the health check, which a
+    // handler for the same route replaces without a conflict.
+    defaults: HashSet<(RequestType, Path)>,
+    // Each route's path as it was registered, by route key, to re-key the
+    // routes when `case_insensitive` changes.
+    spellings: HashMap<(RequestType, Path), Path>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server {
+            get: Router::new(),
+            post: Router::new(),
+            put: Router::new(),
+            delete: Router::new(),
+            patch: Router::new(),
+            byte_order: ByteOrder::default(),
+            fallback: None,
+            workers: thread::available_parallelism().map_or(4, |n| n.get()),
+            max_payload_len: DEFAULT_MAX_PAYLOAD_LEN,
+            route_limits: HashMap::new(),
+            middleware: vec![],
+            state: None,
+            dependencies: Arc::default(),
+            strict_payload: false,
+            metrics: Arc::default(),
+            next_id: Arc::new(AtomicU64::new(1)),
+            handler_timeout: None,
+            case_insensitive: false,
+            trailing_slash: TrailingSlash::default(),
+            verify_checksum: false,
+            on_complete: None,
+            on_unread_payload: None,
+            duplicates: vec![],
+            defaults: HashSet::new(),
+            spellings: HashMap::new(),
+        }
+    }
 }
 
 impl Server {
@@ -15,45 +201,1153 @@ impl Server {
         Self::default()
     }
 
-    pub fn get<P, F, A, R>(&mut self, path: P, f: F) -> &mut Self 
+    pub fn with_byte_order(mut self, order: ByteOrder) -> Self {
+        self.byte_order = order;
+        self
+    }
+
+    /// Limit on the size of request payloads, also applied to the length
+    /// prefixes of `String` and `Vec` arguments. Routes can override it with
+    /// `limit`.
+    pub fn with_max_payload_len(mut self, max_payload_len: usize) -> Self {
+        self.max_payload_len = max_payload_len;
+        self
+    }
+
+    /// Payload size limit for the routes registered with the pattern `path`,
+    /// whatever their method. It takes precedence over the server-wide
+    /// `with_max_payload_len`, whether it is lower or higher.
+    pub fn limit<P>(&mut self, path: P, max_payload_len: usize) -> &mut Self
+    where
+        P: Into<Path>,
+    {
+        let path = self.route_key(path.into());
+        self.route_limits.insert(path, max_payload_len);
+
+        self
+    }
+
+    /// Value handed to every handler taking a `State<T>` argument with the
+    /// same `T`.
+    pub fn with_state<T>(mut self, state: T) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.state = Some(Arc::new(state));
+        self
+    }
+
+    /// Value handed to every handler taking an `Inject<T>` argument with the
+    /// same `T`. Injecting a second value of a type replaces the first.
+    pub fn inject<T>(mut self, value: Arc<T>) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.dependencies).insert(TypeId::of::<T>(), value);
+        self
+    }
+
+    /// Rejects requests with `BadRequest` when the handler arguments leave
+    /// payload bytes unconsumed. Off by default.
+    pub fn strict_payload(mut self, strict: bool) -> Self {
+        self.strict_payload = strict;
+        self
+    }
+
+    /// Deadline for requests served by `serve`. A request taking longer gets
+    /// a `GatewayTimeout` response. Handlers can't be interrupted, so the
+    /// late handler still runs to completion and keeps its worker busy until
+    /// then; only its response is dropped. Handlers can take a `Deadline`
+    /// argument to stop on their own in time.
+    pub fn handler_timeout(mut self, timeout: Duration) -> Self {
+        self.handler_timeout = Some(timeout);
+        self
+    }
+
+    /// Matches the static segments of routes regardless of case. Param and
+    /// wildcard values keep the case they were sent with. Routes registered
+    /// before this is set keep working.
+    ///
+    /// # Panics
+    ///
+    /// If two registered routes only differ in case, like `/Book` and `/book`.
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        if case_insensitive != self.case_insensitive {
+            self.case_insensitive = case_insensitive;
+            self.rekey();
+        }
+        self
+    }
+
+    pub fn trailing_slash(mut self, policy: TrailingSlash) -> Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Makes `serve` expect request frames written by
+    /// `request::encode_with_checksum`. A frame failing the check is answered
+    /// `BadRequest` with the `ChecksumMismatch` it failed with, then the
+    /// connection is closed. Off by default.
+    pub fn verify_checksum(mut self, verify: bool) -> Self {
+        self.verify_checksum = verify;
+        self
+    }
+
+    /// Answers `GET path` with `Ok`, ignoring any payload, for liveness probes.
+    /// A handler registered for `GET path`, before or after, replaces it.
+    pub fn with_health_check<P>(mut self, path: P) -> Self
     where
         P: Into<Path>,
-        A: FromPayload + 'static,
-        R: Into<HttpStatus> + 'static, 
-        F: Factory<A, R> + 'static,
+    {
+        let written = path.into();
+        let path = self.route_key(written.clone());
+        if !self.get.contains_key(&path) {
+            let service = BoxedService::from_handler(Handler::new(|_: RawBody| HttpStatus::Ok));
+            self.get.insert(path.clone(), service);
+            self.spellings.insert((RequestType::Get, path.clone()), written);
+            self.defaults.insert((RequestType::Get, path));
+        }
+        self
+    }
+
+    /// Number of worker threads `serve` runs handlers on. Defaults to the
+    /// available parallelism of the machine.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Replaces a route registered with the same `path` for `method`.
+    ///
+    /// # Panics
+    ///
+    /// If `path` is ambiguous with a registered route, like `/a/:x` and
+    /// `/a/*rest`. `try_route` returns the error instead.
+    pub fn route<P, F, A, R>(&mut self, method: RequestType, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
     {
         let handler = Handler::new(f);
-        self.get.insert(path.into(), BoxedService::from_handler(handler));
+        self.register(method, path.into(), BoxedService::from_handler(handler));
 
         self
     }
 
+    /// Deregisters the route registered with exactly `path` for `method`,
+    /// returning whether there was one.
+    pub fn remove<P>(&mut self, method: RequestType, path: P) -> bool 
+    where
+        P: Into<Path>,
+    {
+        let path = self.route_key(path.into());
+        self.defaults.remove(&(method, path.clone()));
+        self.spellings.remove(&(method, path.clone()));
+        self.services_mut(method).remove(&path).is_some()
+    }
+
+    /// Swaps the handler of an existing route for `f`. Returns `false`, and
+    /// registers nothing, if there is no such route.
+    pub fn replace<P, F, A, R>(&mut self, method: RequestType, path: P, f: F) -> bool 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        let path = self.route_key(path.into());
+        if !self.services(method).contains_key(&path) {
+            return false;
+        }
+        self.services_mut(method).insert(path, BoxedService::from_handler(Handler::new(f)));
+        true
+    }
+
+    /// Registers one handler for each of `methods`, all sharing it.
+    pub fn on<P, F, A, R>(&mut self, methods: &[RequestType], path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        let service = BoxedService::from_handler(Handler::new(f));
+        let path = path.into();
+        for &method in methods {
+            self.register(method, path.clone(), service.clone());
+        }
+
+        self
+    }
+
+    pub fn get<P, F, A, R>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.route(RequestType::Get, path, f)
+    }
+
     pub fn post<P, F, A, R>(&mut self, path: P, f: F) -> &mut Self 
     where
         P: Into<Path>,
-        A: FromPayload + 'static,
-        R: Into<HttpStatus> + 'static, 
-        F: Factory<A, R> + 'static,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.route(RequestType::Post, path, f)
+    }
+
+    pub fn put<P, F, A, R>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.route(RequestType::Put, path, f)
+    }
+
+    pub fn delete<P, F, A, R>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.route(RequestType::Delete, path, f)
+    }
+
+    pub fn patch<P, F, A, R>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.route(RequestType::Patch, path, f)
+    }
+
+    pub fn route_async<P, F, A, Fut>(&mut self, method: RequestType, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        Fut: Future + 'static,
+        Fut::Output: Responder,
+        F: Factory<A, Fut> + Send + Sync + 'static,
     {
         let handler = Handler::new(f);
-        self.post.insert(path.into(), BoxedService::from_handler(handler));
+        self.register(method, path.into(), BoxedService::from_async_handler(handler));
 
         self
     }
 
-    pub fn handle_request(&self, request: Request) -> Result<HttpStatus, String> {
-        let service = match request.ty {
-            RequestType::Get => match self.get.get(&request.path) {
-                Some(s) => s,
-                None => return Err(format!("missing get handler for path {}", request.path)), 
-            },
-            RequestType::Post => match self.post.get(&request.path) {
-                Some(s) => s,
-                None => return Err(format!("missing post handler for path {}", request.path)), 
+    pub fn get_async<P, F, A, Fut>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        Fut: Future + 'static,
+        Fut::Output: Responder,
+        F: Factory<A, Fut> + Send + Sync + 'static,
+    {
+        self.route_async(RequestType::Get, path, f)
+    }
+
+    pub fn post_async<P, F, A, Fut>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        Fut: Future + 'static,
+        Fut::Output: Responder,
+        F: Factory<A, Fut> + Send + Sync + 'static,
+    {
+        self.route_async(RequestType::Post, path, f)
+    }
+
+    pub fn put_async<P, F, A, Fut>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        Fut: Future + 'static,
+        Fut::Output: Responder,
+        F: Factory<A, Fut> + Send + Sync + 'static,
+    {
+        self.route_async(RequestType::Put, path, f)
+    }
+
+    pub fn delete_async<P, F, A, Fut>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        Fut: Future + 'static,
+        Fut::Output: Responder,
+        F: Factory<A, Fut> + Send + Sync + 'static,
+    {
+        self.route_async(RequestType::Delete, path, f)
+    }
+
+    pub fn patch_async<P, F, A, Fut>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        Fut: Future + 'static,
+        Fut::Output: Responder,
+        F: Factory<A, Fut> + Send + Sync + 'static,
+    {
+        self.route_async(RequestType::Patch, path, f)
+    }
+
+    /// Fails if `path` is already registered for `method`, or is ambiguous
+    /// with a route that is, like `/a/:x` and `/a/*rest`. `route` instead
+    /// replaces the duplicate, and panics on an ambiguous route.
+    pub fn try_route<P, F, A, R>(&mut self, method: RequestType, path: P, f: F) -> Result<&mut Self, RouteError> 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        let path = path.into();
+        if let Some(err) = self.route_error(method, &self.route_key(path.clone())) {
+            return Err(err);
+        }
+
+        Ok(self.route(method, path, f))
+    }
+
+    pub fn try_get<P, F, A, R>(&mut self, path: P, f: F) -> Result<&mut Self, RouteError> 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.try_route(RequestType::Get, path, f)
+    }
+
+    pub fn try_post<P, F, A, R>(&mut self, path: P, f: F) -> Result<&mut Self, RouteError> 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.try_route(RequestType::Post, path, f)
+    }
+
+    pub fn try_put<P, F, A, R>(&mut self, path: P, f: F) -> Result<&mut Self, RouteError> 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.try_route(RequestType::Put, path, f)
+    }
+
+    pub fn try_delete<P, F, A, R>(&mut self, path: P, f: F) -> Result<&mut Self, RouteError> 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.try_route(RequestType::Delete, path, f)
+    }
+
+    pub fn try_patch<P, F, A, R>(&mut self, path: P, f: F) -> Result<&mut Self, RouteError> 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.try_route(RequestType::Patch, path, f)
+    }
+
+    pub fn fallback<F, A, R>(&mut self, f: F) -> &mut Self 
+    where
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        let handler = Handler::new(f);
+        self.fallback = Some(BoxedService::from_handler(handler));
+
+        self
+    }
+
+    pub fn group<P, G>(&mut self, prefix: P, g: G) -> &mut Self 
+    where
+        P: Into<Path>,
+        G: FnOnce(&mut Group<'_>),
+    {
+        let mut group = Group { server: self, prefix: prefix.into() };
+        g(&mut group);
+
+        self
+    }
+
+    /// Moves every route of `sub` under `prefix`. Nothing is registered if any
+    /// of the prefixed routes already exists on `self` or is ambiguous with
+    /// one that does.
+    pub fn mount<P>(&mut self, prefix: P, mut sub: Server) -> Result<&mut Self, Vec<RouteError>> 
+    where
+        P: Into<Path>,
+    {
+        let prefix = prefix.into();
+
+        let written = |sub: &Server, method, key: &Path| sub.spellings.get(&(method, key.clone())).unwrap_or(key).prefixed(&prefix);
+
+        let conflicts: Vec<RouteError> = RequestType::ALL
+            .into_iter()
+            .flat_map(|method| sub.services(method).keys().map(move |path| (method, path)))
+            .filter_map(|(method, path)| self.route_error(method, &self.route_key(written(&sub, method, path))))
+            .collect();
+        if !conflicts.is_empty() {
+            return Err(conflicts);
+        }
+
+        for method in RequestType::ALL {
+            let routes: Vec<_> = sub.services_mut(method).drain().collect();
+            for (key, service) in routes {
+                let written = written(&sub, method, &key);
+                let path = self.route_key(written.clone());
+                self.defaults.remove(&(method, path.clone()));
+                if sub.defaults.contains(&(method, key)) {
+                    self.defaults.insert((method, path.clone()));
+                }
+                self.spellings.insert((method, path.clone()), written);
+                self.services_mut(method).insert(path, service);
+            }
+        }
+        for err in sub.duplicates.drain(..) {
+            if let RouteError::Conflict { method, path } = err {
+                let path = self.route_key(path.prefixed(&prefix));
+                self.duplicates.push(RouteError::Conflict { method, path });
+            }
+        }
+        for (path, max_len) in sub.route_limits.drain() {
+            let path = self.route_key(path.prefixed(&prefix));
+            self.route_limits.insert(path, max_len);
+        }
+
+        Ok(self)
+    }
+
+    /// Middleware runs in the order it was added, the first one being the
+    /// outermost.
+    pub fn wrap<M>(&mut self, middleware: M) -> &mut Self 
+    where
+        M: Middleware + 'static,
+    {
+        self.middleware.push(Arc::new(middleware));
+
+        self
+    }
+
+    /// Calls `hook` after every request, whether it matched a route or not.
+    /// Replaces any hook set before.
+    pub fn on_complete<H>(&mut self, hook: H) -> &mut Self
+    where
+        H: Fn(&RequestInfo) + Send + Sync + 'static,
+    {
+        self.on_complete = Some(Arc::new(hook));
+
+        self
+    }
+
+    /// Calls `hook` with the request path and the number of bytes left when
+    /// a handler's arguments don't read the whole payload, instead of logging
+    /// it. Requests in `strict_payload` mode are rejected instead. Replaces
+    /// any hook set before.
+    pub fn on_unread_payload<H>(&mut self, hook: H) -> &mut Self
+    where
+        H: Fn(&Path, usize) + Send + Sync + 'static,
+    {
+        self.on_unread_payload = Some(Arc::new(hook));
+
+        self
+    }
+
+    /// Streamed bodies are read into memory before this returns.
+    pub fn handle_request(&self, request: Request) -> ServiceResponse {
+        service::buffered(self.respond(request, self.deadline()))
+    }
+
+    // When a handler started now has to be done by.
+    fn deadline(&self) -> Option<Instant> {
+        self.handler_timeout.map(|timeout| Instant::now() + timeout)
+    }
+
+    // Handles `request` like `handle_request`, but without middleware a
+    // streamed body is left for the connection to read as it writes it.
+    fn respond(&self, request: Request, deadline: Option<Instant>) -> (HttpStatus, Body) {
+        let id = RequestId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let start = Instant::now();
+        let response = match self.middleware.is_empty() {
+            true => self.dispatch_body(&request, id, deadline),
+            false => {
+                let (status, body) = self.run_middleware(0, &request, id, deadline);
+                (status, Body::Bytes(body))
+            }
+        };
+        let elapsed = start.elapsed();
+
+        self.metrics.record_total(response.0);
+        if let Some(hook) = &self.on_complete {
+            hook(&RequestInfo { id, method: request.ty, path: &request.path, status: response.0, elapsed });
+        }
+        response
+    }
+
+    /// Counts of the requests handled so far, in total and per route.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.snapshot()
+    }
+
+    fn run_middleware(&self, index: usize, request: &Request, id: RequestId, deadline: Option<Instant>) -> ServiceResponse {
+        match self.middleware.get(index) {
+            Some(middleware) => middleware.handle(request, &|req| self.run_middleware(index + 1, req, id, deadline)),
+            None => self.dispatch(request, id, deadline),
+        }
+    }
+
+    fn dispatch(&self, request: &Request, id: RequestId, deadline: Option<Instant>) -> ServiceResponse {
+        service::buffered(self.dispatch_body(request, id, deadline))
+    }
+
+    fn dispatch_body(&self, request: &Request, id: RequestId, deadline: Option<Instant>) -> (HttpStatus, Body) {
+        let (pattern, service, payload) = match self.prepare(request, id, deadline) {
+            Ok(prepared) => prepared,
+            Err(((status, body), err)) => {
+                match err {
+                    PayloadError::TooLarge { len, max } => println!("payload of {len} bytes exceeds the limit of {max}"),
+                    PayloadError::NoRoute if status != HttpStatus::PermanentRedirect => {
+                        println!("missing {} handler for path {}", request.ty, request.path);
+                    }
+                    _ => {}
+                }
+                return (status, Body::Bytes(body));
+            }
+        };
+
+        let response = service.handle_body(payload);
+        if let Some(pattern) = pattern {
+            self.metrics.record_route(request.ty, pattern, response.0);
+        }
+        response
+    }
+
+    /// Routes `request` and extracts the arguments of its handler like
+    /// `handle_request` would, without calling the handler. Middleware is
+    /// skipped. Fails with `NoRoute` if no route or fallback would handle it.
+    /// Nothing is logged, and the unread payload hook isn't called.
+    pub fn validate_request(&self, request: &Request) -> Result<(), PayloadError> {
+        match self.prepare(request, RequestId(0), self.deadline()) {
+            Ok((_, service, payload)) => service.check(payload.dry_run()),
+            Err((_, err)) => Err(err),
+        }
+    }
+
+    // Finds the service for `request` and the payload to call it with. A
+    // request no service takes gets the response to send, along with the
+    // reason `validate_request` reports, which leaves the logging to the
+    // caller. `deadline` is the one the caller enforces, so handlers see the
+    // time they actually have left.
+    #[allow(clippy::type_complexity)]
+    fn prepare<'r>(&self, request: &'r Request, id: RequestId, deadline: Option<Instant>) -> Result<(Option<&Path>, &BoxedService, Payload<'r>), (ServiceResponse, PayloadError)> {
+        let found = match self.find_with_policy(request.ty, &request.path) {
+            Ok(found) => found,
+            Err(redirect) => return Err((redirect, PayloadError::NoRoute)),
+        };
+
+        let (pattern, service, params) = match (found, &self.fallback) {
+            (Some((pattern, service, params)), _) => (Some(pattern), service, params),
+            (None, Some(fallback)) => (None, fallback, Params::default()),
+            (None, None) => {
+                let allowed = self.allowed_methods(&request.path);
+                if allowed.is_empty() {
+                    let response = (HttpStatus::NotFound, format!("no route for {}", request.path).to_payload());
+                    return Err((response, PayloadError::NoRoute));
+                }
+                let allowed: Vec<String> = allowed.iter().map(ToString::to_string).collect();
+                return Err(((HttpStatus::MethodNotAllowed, allowed.to_payload()), PayloadError::NoRoute));
+            }
+        };
+
+        // Only a request some service takes is checked against a limit, so
+        // one without a route is still told so.
+        let max_len = pattern
+            .and_then(|pattern| self.route_limits.get(pattern))
+            .copied()
+            .unwrap_or(self.max_payload_len);
+        let len = request.payload.len();
+        if len > max_len {
+            return Err(((HttpStatus::PayloadTooLarge, vec![]), PayloadError::TooLarge { len, max: max_len }));
+        }
+
+        let mut payload = Payload::from_bytes(&request.payload)
+            .with_byte_order(self.byte_order)
+            .with_max_len(max_len)
+            .with_strict(self.strict_payload)
+            .with_params(params)
+            .with_query(request.query.clone())
+            .with_request(request.ty, request.path.clone())
+            .with_request_id(id);
+        if let Some(pattern) = pattern {
+            payload = payload.with_pattern(pattern.clone());
+        }
+        if let Some(deadline) = deadline {
+            payload = payload.with_deadline(deadline);
+        }
+        if let Some(state) = &self.state {
+            payload = payload.with_state(state.clone());
+        }
+        if !self.dependencies.is_empty() {
+            payload = payload.with_dependencies(self.dependencies.clone());
+        }
+        if let Some(hook) = &self.on_unread_payload {
+            payload = payload.with_unread_hook(hook.clone());
+        }
+
+        Ok((pattern, service, payload))
+    }
+
+    /// Checks the whole route table before serving: every route that
+    /// replaced an earlier one (`try_route` would have rejected it), and every
+    /// pattern with an empty segment or an unnamed param, like `/a//b` or
+    /// `/a/:`, or a wildcard before the last segment. Handlers taking an `Inject<T>` for a `T` that wasn't injected
+    /// fail it too. All problems are returned at once. Ambiguous routes never
+    /// get this far, registering one panics.
+    pub fn finalize(self) -> Result<FinalizedServer, Vec<RouteError>> {
+        let mut errors = self.duplicates.clone();
+        for method in RequestType::ALL {
+            let mut services: Vec<_> = self.services(method).entries().collect();
+            services.sort_by_key(|(path, _)| *path);
+            for (path, service) in services {
+                if has_empty_segment(path) {
+                    errors.push(RouteError::EmptySegment { method, path: path.clone() });
+                }
+                if has_misplaced_wildcard(path) {
+                    errors.push(RouteError::MisplacedWildcard { method, path: path.clone() });
+                }
+                for &(ty, name) in service.dependencies() {
+                    if !self.dependencies.contains_key(&ty) {
+                        errors.push(RouteError::MissingDependency { method, path: path.clone(), ty: name });
+                    }
+                }
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(FinalizedServer(self)),
+            false => Err(errors),
+        }
+    }
+
+    pub(crate) fn serve(self, addr: &str) -> io::Result<()> {
+        self.serve_listener(TcpListener::bind(addr)?)
+    }
+
+    pub(crate) fn serve_listener(self, listener: TcpListener) -> io::Result<()> {
+        self.serve_until(listener, Shutdown::new())
+    }
+
+    pub(crate) fn serve_until(self, listener: TcpListener, shutdown: Shutdown) -> io::Result<()> {
+        let pool = Arc::new(ThreadPool::new(self.workers));
+        let server = Arc::new(self);
+        let mut connections: Vec<thread::JoinHandle<()>> = vec![];
+
+        let result = accept_connections(&server, &pool, &listener, &shutdown, &mut connections);
+
+        // Even when accepting failed for good, the requests already read are
+        // answered before returning.
+        for connection in connections {
+            let _ = connection.join();
+        }
+        result
+    }
+
+    /// Number of registered routes across all methods, not counting the
+    /// fallback.
+    pub fn len(&self) -> usize {
+        RequestType::ALL.into_iter().map(|method| self.services(method).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn routes(&self) -> Vec<(RequestType, String)> {
+        let mut routes: Vec<(RequestType, String)> = RequestType::ALL
+            .into_iter()
+            .flat_map(|method| self.services(method).keys().map(move |path| (method, path.as_str().to_string())))
+            .collect();
+        routes.sort();
+        routes
+    }
+
+    pub fn allowed_methods(&self, path: &Path) -> Vec<RequestType> {
+        RequestType::ALL
+            .into_iter()
+            .filter(|method| self.find(*method, path).is_some())
+            .collect()
+    }
+
+    // Static routes take precedence over `:param` routes, which take
+    // precedence over `*wildcard` routes. See `Router`.
+    fn find(&self, method: RequestType, path: &Path) -> Option<(&Path, &BoxedService, Params)> {
+        self.services(method).find(path, self.case_insensitive)
+    }
+
+    // Falls back to the path with the trailing slash added or removed, as the
+    // trailing slash policy allows. `Err` holds a redirect response.
+    fn find_with_policy(&self, method: RequestType, path: &Path) -> Result<Option<(&Path, &BoxedService, Params)>, ServiceResponse> {
+        if let Some(found) = self.find(method, path) {
+            return Ok(Some(found));
+        }
+
+        match (self.trailing_slash, path.toggled_trailing_slash()) {
+            (TrailingSlash::Strict, _) | (_, None) => Ok(None),
+            (TrailingSlash::Ignore, Some(other)) => Ok(self.find(method, &other)),
+            (TrailingSlash::RedirectToCanonical, Some(other)) => match self.find(method, &other) {
+                Some(_) => Err((HttpStatus::PermanentRedirect, other.as_str().to_string().to_payload())),
+                None => Ok(None),
             },
+        }
+    }
+
+    fn route_key(&self, path: Path) -> Path {
+        if self.case_insensitive {
+            path.lowercased()
+        } else {
+            path
+        }
+    }
+
+    fn services(&self, method: RequestType) -> &Router<BoxedService> {
+        match method {
+            RequestType::Get => &self.get,
+            RequestType::Post => &self.post,
+            RequestType::Put => &self.put,
+            RequestType::Delete => &self.delete,
+            RequestType::Patch => &self.patch,
+        }
+    }
+
+    // The largest payload any route accepts, checked before the frame is read
+    // and its route known.
+    fn frame_limit(&self) -> usize {
+        self.route_limits.values().copied().fold(self.max_payload_len, usize::max)
+    }
+
+    // Inserts into the router under the route key of `written`. Remembers a
+    // replaced route other than a default one, and panics on an ambiguous one
+    // or one only differing in case from the route it would replace.
+    fn register(&mut self, method: RequestType, written: Path, service: BoxedService) {
+        let path = self.route_key(written.clone());
+        if let Some(existing) = self.services(method).ambiguous_with(&path) {
+            panic!("{}", RouteError::Ambiguous { method, path: path.clone(), existing: existing.clone() });
+        }
+        let replaces_default = self.defaults.remove(&(method, path.clone()));
+        if !replaces_default && self.services(method).contains_key(&path) {
+            let conflict = RouteError::Conflict { method, path: path.clone() };
+            if let Some(existing) = self.spellings.get(&(method, path.clone())).filter(|existing| **existing != written) {
+                panic!("{conflict} as {existing}, regardless of case");
+            }
+            self.duplicates.push(conflict);
+        }
+        self.spellings.insert((method, path.clone()), written);
+        self.services_mut(method).insert(path, service);
+    }
+
+    // Moves every route, and the limits and defaults that refer to it, to the
+    // route key of the path it was registered with.
+    fn rekey(&mut self) {
+        let spellings = std::mem::take(&mut self.spellings);
+        let defaults = std::mem::take(&mut self.defaults);
+        let mut keys = HashMap::new();
+
+        for method in RequestType::ALL {
+            let routes: Vec<_> = self.services_mut(method).drain().collect();
+            for (key, service) in routes {
+                let written = spellings.get(&(method, key.clone())).cloned().unwrap_or_else(|| key.clone());
+                let path = self.route_key(written.clone());
+                if self.services(method).contains_key(&path) {
+                    panic!("{}", RouteError::Conflict { method, path });
+                }
+
+                if defaults.contains(&(method, key.clone())) {
+                    self.defaults.insert((method, path.clone()));
+                }
+                keys.insert(key, path.clone());
+                self.spellings.insert((method, path.clone()), written);
+                self.services_mut(method).insert(path, service);
+            }
+        }
+
+        let rekeyed = |path: Path| keys.get(&path).cloned().unwrap_or(path);
+        self.route_limits = std::mem::take(&mut self.route_limits)
+            .into_iter()
+            .map(|(path, max_len)| (self.route_key(rekeyed(path)), max_len))
+            .collect();
+        for err in &mut self.duplicates {
+            if let RouteError::Conflict { path, .. } = err {
+                *path = rekeyed(path.clone());
+            }
+        }
+    }
+
+    fn route_error(&self, method: RequestType, path: &Path) -> Option<RouteError> {
+        let services = self.services(method);
+        if services.contains_key(path) && !self.defaults.contains(&(method, path.clone())) {
+            return Some(RouteError::Conflict { method, path: path.clone() });
+        }
+        if has_misplaced_wildcard(path) {
+            return Some(RouteError::MisplacedWildcard { method, path: path.clone() });
+        }
+        services
+            .ambiguous_with(path)
+            .map(|existing| RouteError::Ambiguous { method, path: path.clone(), existing: existing.clone() })
+    }
+
+    fn services_mut(&mut self, method: RequestType) -> &mut Router<BoxedService> {
+        match method {
+            RequestType::Get => &mut self.get,
+            RequestType::Post => &mut self.post,
+            RequestType::Put => &mut self.put,
+            RequestType::Delete => &mut self.delete,
+            RequestType::Patch => &mut self.patch,
+        }
+    }
+}
+
+/// Builds a `Server` from a table of routes, each a method, a path and a
+/// handler, as if registered one by one:
+///
+///   let server = routes! { GET "/" => success, POST "/bill" => post_bill };
+#[macro_export]
+macro_rules! routes {
+    ($($method: ident $path: literal => $handler: expr),* $(,)?) => {{
+        let mut server = $crate::server::Server::new();
+        $(server.route($crate::routes!(@method $method), $path, $handler);)*
+        server
+    }};
+    (@method GET) => { $crate::request::RequestType::Get };
+    (@method POST) => { $crate::request::RequestType::Post };
+    (@method PUT) => { $crate::request::RequestType::Put };
+    (@method DELETE) => { $crate::request::RequestType::Delete };
+    (@method PATCH) => { $crate::request::RequestType::Patch };
+}
+
+// How often blocked accepts and reads check whether shutdown began.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Tells `FinalizedServer::serve_until` to stop. Clones share the same signal.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    triggered: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn trigger(&self) {
+        self.triggered.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.load(Ordering::SeqCst)
+    }
+}
+
+// Serves framed requests off `stream` until the peer closes it. Every
+// request frame is answered with one response frame, in order.
+fn handle_connection(server: &Arc<Server>, pool: &ThreadPool, shutdown: &Shutdown, mut stream: TcpStream) -> io::Result<()> {
+    while wait_for_frame(&stream, shutdown)? {
+        let request = match read_request(&mut stream, server.frame_limit(), server.verify_checksum) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) => {
+                // Unlike a truncated or malformed frame, a corrupted one was read
+                // whole, so the client can be told why it's dropped.
+                if let Some(err @ PayloadError::ChecksumMismatch { .. }) = e.get_ref().and_then(|e| e.downcast_ref::<PayloadError>()) {
+                    write_response(&mut stream, HttpStatus::BadRequest, &err.to_string().to_payload())?;
+                }
+                return Err(e);
+            }
         };
 
-        let payload = Payload::from_bytes(&request.payload);
-        Ok(service.handle(payload).into())
+        // The timer starts before the request waits in the pool queue, and
+        // the handler is given the same deadline.
+        let deadline = server.deadline();
+        let (tx, rx) = mpsc::channel();
+        let worker_server = server.clone();
+        pool.execute(move || {
+            let _ = tx.send(worker_server.respond(request, deadline));
+        });
+
+        let response = match deadline {
+            Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())).map_err(|e| match e {
+                mpsc::RecvTimeoutError::Timeout => HttpStatus::GatewayTimeout,
+                mpsc::RecvTimeoutError::Disconnected => HttpStatus::InternalServerError,
+            }),
+            None => rx.recv().map_err(|_| HttpStatus::InternalServerError),
+        };
+        let (status, body) = response.unwrap_or_else(|status| (status, Body::Bytes(vec![])));
+        match body {
+            Body::Bytes(body) => write_response(&mut stream, status, &body)?,
+            Body::Stream(body) => write_streamed_response(&mut stream, status, body)?,
+        }
+    }
+
+    Ok(())
+}
+
+// Accepts connections, each handled on its own thread pushed to
+// `connections`, until shutdown begins or accepting fails for good.
+fn accept_connections(
+    server: &Arc<Server>,
+    pool: &Arc<ThreadPool>,
+    listener: &TcpListener,
+    shutdown: &Shutdown,
+    connections: &mut Vec<thread::JoinHandle<()>>,
+) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    while !shutdown.is_triggered() {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(e) if is_transient(&e) => {
+                println!("accept failed: {e}");
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if let Err(e) = stream.set_nonblocking(false) {
+            println!("connection failed: {e}");
+            continue;
+        }
+
+        let (server, pool, shutdown) = (server.clone(), pool.clone(), shutdown.clone());
+        connections.retain(|c| !c.is_finished());
+        connections.push(thread::spawn(move || {
+            if let Err(e) = handle_connection(&server, &pool, &shutdown, stream) {
+                println!("connection failed: {e}");
+            }
+        }));
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+// Accept errors that concern one connection, or a momentary lack of
+// resources like running out of file descriptors, after which accepting
+// again can succeed. `io::ErrorKind` has no stable kind for running out of
+// file descriptors, so those are told by their errno, which is only checked
+// on targets whose values are known: 23 and 24 are `ENFILE` and `EMFILE`
+// in the errno headers of Linux, Android, macOS, iOS and the BSDs.
+fn is_transient(err: &io::Error) -> bool {
+    const ENFILE: i32 = 23;
+    const EMFILE: i32 = 24;
+    const KNOWN_ERRNO: bool = cfg!(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+    ));
+
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset | io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::OutOfMemory
+    ) || (KNOWN_ERRNO && matches!(err.raw_os_error(), Some(ENFILE | EMFILE)))
+}
+
+// Blocks until the next frame starts, returning `false` if the stream ends
+// or shutdown begins first.
+fn wait_for_frame(stream: &TcpStream, shutdown: &Shutdown) -> io::Result<bool> {
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let ready = loop {
+        if shutdown.is_triggered() {
+            break false;
+        }
+        match stream.peek(&mut [0u8; 1]) {
+            Ok(n) => break n > 0,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e),
+        }
+    };
+    stream.set_read_timeout(None)?;
+    Ok(ready)
+}
+
+// Reads one frame as laid out on `request::encode`, with a checksum trailer if
+// `checksum` is set, or `None` if the stream ends before a new frame starts.
+// Frames declaring a payload longer than `max_payload_len` are rejected
+// before the payload is read.
+fn read_request(stream: &mut impl Read, max_payload_len: usize, checksum: bool) -> io::Result<Option<Request>> {
+    let mut frame = vec![0u8; 1];
+    match stream.read_exact(&mut frame) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    read_chunk(stream, &mut frame, 2)?;
+    let path_len = u16::from_be_bytes([frame[1], frame[2]]) as u64;
+    read_chunk(stream, &mut frame, path_len + 4)?;
+    let payload_len = u32::from_be_bytes(frame[frame.len() - 4..].try_into().unwrap()) as u64;
+    if payload_len > max_payload_len as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "payload exceeds the size limit"));
+    }
+    read_chunk(stream, &mut frame, payload_len)?;
+
+    let request = match checksum {
+        true => {
+            read_chunk(stream, &mut frame, 4)?;
+            request::decode_with_checksum(&frame)
+        }
+        false => request::decode(&frame),
+    };
+    request
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn read_chunk(stream: &mut impl Read, frame: &mut Vec<u8>, len: u64) -> io::Result<()> {
+    let start = frame.len();
+    stream.take(len).read_to_end(frame)?;
+    if (frame.len() - start) as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(())
+}
+
+// A response frame is the `u16` status code, a `u32` body length and the body.
+// A body too long for its length, which can't be `STREAMED_LEN` either, is
+// answered `InternalServerError` instead.
+fn write_response(stream: &mut impl Write, status: HttpStatus, body: &[u8]) -> io::Result<()> {
+    let (status, body, len) = match u32::try_from(body.len()) {
+        Ok(len) if len != STREAMED_LEN => (status, body, len),
+        _ => {
+            println!("response body of {} bytes is too long to frame", body.len());
+            (HttpStatus::InternalServerError, &[][..], 0)
+        }
+    };
+    stream.write_all(&status.code().to_be_bytes())?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+// Written as the body length of a streamed response, whose body follows as
+// chunks of a `u32` length and that many bytes, ending with an empty chunk.
+const STREAMED_LEN: u32 = u32::MAX;
+const STREAM_CHUNK_LEN: usize = 64 * 1024;
+
+// Sends the status as soon as it's known. A body that fails to read ends the
+// response without its empty chunk, and the error closes the connection.
+fn write_streamed_response(stream: &mut impl Write, status: HttpStatus, mut body: impl Read) -> io::Result<()> {
+    stream.write_all(&status.code().to_be_bytes())?;
+    stream.write_all(&STREAMED_LEN.to_be_bytes())?;
+    stream.flush()?;
+
+    let mut chunk = vec![0u8; STREAM_CHUNK_LEN];
+    loop {
+        let n = match body.read(&mut chunk) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        stream.write_all(&(n as u32).to_be_bytes())?;
+        stream.write_all(&chunk[..n])?;
+        stream.flush()?;
+        if n == 0 {
+            return Ok(());
+        }
+    }
+}
+
+pub struct Group<'s> {
+    server: &'s mut Server,
+    prefix: Path,
+}
+
+impl Group<'_> {
+    pub fn route<P, F, A, R>(&mut self, method: RequestType, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        let path = path.into().prefixed(&self.prefix);
+        self.server.route(method, path, f);
+
+        self
+    }
+
+    pub fn get<P, F, A, R>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.route(RequestType::Get, path, f)
+    }
+
+    pub fn post<P, F, A, R>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.route(RequestType::Post, path, f)
+    }
+
+    pub fn put<P, F, A, R>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.route(RequestType::Put, path, f)
+    }
+
+    pub fn delete<P, F, A, R>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.route(RequestType::Delete, path, f)
+    }
+
+    pub fn patch<P, F, A, R>(&mut self, path: P, f: F) -> &mut Self 
+    where
+        P: Into<Path>,
+        A: 'static,
+        R: Responder + 'static, 
+        F: Factory<A, R> + Send + Sync + 'static,
+    {
+        self.route(RequestType::Patch, path, f)
+    }
+}
@@ -1,59 +1,679 @@
+use std::any::{self, Any, TypeId};
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fmt::Display;
+use std::hash::Hash;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::executor::block_on;
 use crate::handler::{Factory, Handler};
+use crate::request::{Deadline, FullPath, MatchedPath, Method, Params, Path, Query, Raw, Request, RequestId, RequestType};
+
+pub use server_in_rust_derive::{FromPayload, ToPayload};
+
+/// Implements `FromPayload` for types with a `TryFrom<u32>` impl, like
+/// C-style enums with a fixed set of integer codes. The code is read as a
+/// `u32`, and a code `try_from` rejects fails with `InvalidDiscriminant`:
+///
+///   impl TryFrom<u32> for Color { ... }
+///   from_payload_via_try_from!(Color);
+#[macro_export]
+macro_rules! from_payload_via_try_from {
+    ($($T: ty),+ $(,)?) => {$(
+        impl<'a> $crate::service::FromPayload<'a> for $T {
+            fn from(payload: &mut $crate::service::Payload<'a>) -> ::std::result::Result<Self, $crate::service::PayloadError> {
+                let code = <u32 as $crate::service::FromPayload>::from(payload)?;
+                <$T as ::std::convert::TryFrom<u32>>::try_from(code)
+                    .map_err(|_| $crate::service::PayloadError::InvalidDiscriminant(code))
+            }
+        }
+    )+};
+}
+
+/// Implements `ToPayload` for `Copy` types with a `From<T> for u32` impl,
+/// writing the code `from_payload_via_try_from!` reads:
+///
+///   impl From<Color> for u32 { ... }
+///   to_payload_via_into!(Color);
+#[macro_export]
+macro_rules! to_payload_via_into {
+    ($($T: ty),+ $(,)?) => {$(
+        impl $crate::service::ToPayload for $T {
+            fn to_payload(&self) -> ::std::vec::Vec<u8> {
+                <u32 as $crate::service::ToPayload>::to_payload(&<u32 as ::std::convert::From<$T>>::from(*self))
+            }
+        }
+    )+};
+}
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpStatus {
-    Success,
-    Failed,
+    Ok,
+    Created,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    InternalServerError,
+    GatewayTimeout,
+}
+
+#[allow(non_upper_case_globals)]
+impl HttpStatus {
+    pub const Success: HttpStatus = HttpStatus::Ok;
+    pub const Failed: HttpStatus = HttpStatus::InternalServerError;
+
+    pub fn code(&self) -> u16 {
+        match self {
+            HttpStatus::Ok => 200,
+            HttpStatus::Created => 201,
+            HttpStatus::PermanentRedirect => 308,
+            HttpStatus::BadRequest => 400,
+            HttpStatus::Unauthorized => 401,
+            HttpStatus::NotFound => 404,
+            HttpStatus::MethodNotAllowed => 405,
+            HttpStatus::PayloadTooLarge => 413,
+            HttpStatus::InternalServerError => 500,
+            HttpStatus::GatewayTimeout => 504,
+        }
+    }
+
+    /// A response with this status and a human-readable `message` as body,
+    /// like `HttpStatus::BadRequest.with_message("missing field foo")`. The
+    /// message is sent as a length-prefixed `String` body, a `u32` length and
+    /// then its UTF-8 bytes, not as the bare bytes of a `RawBody` or `&[u8]`
+    /// body. Clients decode it as a `String`.
+    pub fn with_message(self, message: impl Into<String>) -> (HttpStatus, String) {
+        (self, message.into())
+    }
+}
+
+pub type ServiceResponse = (HttpStatus, Vec<u8>);
+
+pub trait Responder {
+    fn respond(self) -> Result<ServiceResponse, String>;
+
+    /// Like `respond`, but the body may be left as a stream to be read while
+    /// it's sent. Only streamed responses need to override it.
+    fn respond_body(self) -> Result<(HttpStatus, Body), String>
+    where
+        Self: Sized,
+    {
+        self.respond().map(|(status, body)| (status, Body::Bytes(body)))
+    }
+}
+
+/// A response body, either in memory or read as it's written to the
+/// connection.
+pub enum Body {
+    Bytes(Vec<u8>),
+    Stream(StreamBody),
+}
+
+impl Body {
+    /// Reads a streamed body to the end.
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Body::Bytes(bytes) => Ok(bytes),
+            Body::Stream(mut stream) => {
+                let mut bytes = vec![];
+                stream.read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+/// A body that `serve` sends in chunks as it's read, instead of buffering it,
+/// returned by handlers as `(HttpStatus, StreamBody)`. The status is sent
+/// before any of the body, so a read error halfway through can't change it
+/// and closes the connection instead. `Server::handle_request`, and servers
+/// with middleware, read the whole body into memory.
+pub struct StreamBody(Box<dyn Read + Send>);
+
+impl StreamBody {
+    pub fn new<R>(reader: R) -> Self
+    where
+        R: Read + Send + 'static,
+    {
+        StreamBody(Box::new(reader))
+    }
+
+    /// A body made of `chunks` in order, each only produced once the ones
+    /// before it were read.
+    pub fn from_chunks<I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static,
+    {
+        StreamBody::new(Chunks { chunks: chunks.into_iter(), current: vec![], pos: 0 })
+    }
+}
+
+impl Read for StreamBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+struct Chunks<I> {
+    chunks: I,
+    current: Vec<u8>,
+    pos: usize,
+}
+
+impl<I> Read for Chunks<I>
+where I: Iterator<Item = Vec<u8>>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.current.len() {
+            match self.chunks.next() {
+                Some(chunk) => (self.current, self.pos) = (chunk, 0),
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.current.len() - self.pos);
+        buf[..n].copy_from_slice(&self.current[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Responder for (HttpStatus, StreamBody) {
+    fn respond(self) -> Result<ServiceResponse, String> {
+        let body = Body::Stream(self.1).into_bytes().map_err(|e| e.to_string())?;
+        Ok((self.0, body))
+    }
+
+    fn respond_body(self) -> Result<(HttpStatus, Body), String> {
+        Ok((self.0, Body::Stream(self.1)))
+    }
+}
+
+impl<T> Responder for T
+where T: Into<HttpStatus>
+{
+    fn respond(self) -> Result<ServiceResponse, String> {
+        Ok((self.into(), vec![]))
+    }
 }
 
+impl<T> Responder for (HttpStatus, T)
+where T: ToPayload
+{
+    fn respond(self) -> Result<ServiceResponse, String> {
+        Ok((self.0, self.1.to_payload()))
+    }
+}
+
+/// A handler return value with a status and a body, equivalent to the tuple
+/// `(HttpStatus, T)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response<T> {
+    pub status: HttpStatus,
+    pub body: T,
+}
+
+impl<T> Response<T> {
+    pub fn new(status: HttpStatus, body: T) -> Self {
+        Response { status, body }
+    }
+
+    pub fn ok(body: T) -> Self {
+        Response::new(HttpStatus::Ok, body)
+    }
+}
+
+impl<T> Responder for Response<T>
+where T: ToPayload
+{
+    fn respond(self) -> Result<ServiceResponse, String> {
+        Ok((self.status, self.body.to_payload()))
+    }
+}
+
+/// Lets handlers `?` on I/O errors when they return `Result<_, HttpStatus>`,
+/// answering `InternalServerError`. The error itself is dropped; handlers
+/// returning `Result<_, io::Error>` get it logged instead.
+impl From<std::io::Error> for HttpStatus {
+    fn from(_: std::io::Error) -> Self {
+        HttpStatus::InternalServerError
+    }
+}
+
+/// An error status is answered as it is, with no body.
+impl<T> Responder for Result<T, HttpStatus>
+where T: Responder
+{
+    fn respond(self) -> Result<ServiceResponse, String> {
+        match self {
+            Ok(t) => t.respond(),
+            Err(status) => Ok((status, vec![])),
+        }
+    }
+
+    fn respond_body(self) -> Result<(HttpStatus, Body), String> {
+        match self {
+            Ok(t) => t.respond_body(),
+            Err(status) => Ok((status, Body::Bytes(vec![]))),
+        }
+    }
+}
+
+/// Any other error is logged and answered `InternalServerError`.
+impl<T, E> Responder for Result<T, E>
+where
+    T: Responder,
+    E: Display,
+{
+    fn respond(self) -> Result<ServiceResponse, String> {
+        match self {
+            Ok(t) => t.respond(),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn respond_body(self) -> Result<(HttpStatus, Body), String> {
+        match self {
+            Ok(t) => t.respond_body(),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+// Every service is boxed, even zero-argument and single-scalar handlers: the
+// route table holds handlers of different closure types, so calling one
+// without a virtual call would need its concrete type at the call site.
+// `benches/dispatch.rs` puts the boxed call at a small part of a full
+// `handle_request`, most of which is routing and payload setup.
+type Service = Arc<dyn Fn(Payload<'_>) -> (HttpStatus, Body) + Send + Sync>;
+type Check = Arc<dyn Fn(Payload<'_>) -> Result<(), PayloadError> + Send + Sync>;
+
+#[derive(Clone)]
 pub struct BoxedService {
-    service: Box<dyn Fn(Payload) -> HttpStatus>,
+    service: Service,
+    // Extracts the handler arguments without calling the handler.
+    check: Check,
+    dependencies: Vec<(TypeId, &'static str)>,
 }
 
 impl BoxedService {
     pub fn from_handler<F, Args, Res>(handler: Handler<F, Args, Res>) -> Self 
     where
-        Args: FromPayload + 'static,
-        Res: Into<HttpStatus> + 'static,
-        F: Factory<Args, Res> + 'static,
+        Args: 'static,
+        Res: Responder + 'static,
+        F: Factory<Args, Res> + Send + Sync + 'static,
     {
-        let service = Box::new(move |mut payload| {
-            match Args::from(&mut payload) {
-                Ok(args) => handler.call(args).into(),
-                Err(msg) => {
-                    println!("{msg}");
-                    HttpStatus::Failed
-                }
+        let handler = Arc::new(handler);
+        let check = checker(handler.clone());
+        let dependencies = handler.dependencies();
+        let service = Arc::new(move |mut payload: Payload<'_>| {
+            match handler.call(&mut payload) {
+                Ok(res) => into_response(res),
+                Err(err) => extraction_failed(err),
+            }
+        });
+
+        BoxedService { service, check, dependencies }
+    }
+
+    /// Async handlers are driven to completion on the thread handling the
+    /// request, so a pending future blocks that thread until it is woken.
+    pub fn from_async_handler<F, Args, Fut>(handler: Handler<F, Args, Fut>) -> Self 
+    where
+        Args: 'static,
+        Fut: Future + 'static,
+        Fut::Output: Responder,
+        F: Factory<Args, Fut> + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        let check = checker(handler.clone());
+        let dependencies = handler.dependencies();
+        let service = Arc::new(move |mut payload: Payload<'_>| {
+            match handler.call(&mut payload) {
+                Ok(fut) => into_response(block_on(fut)),
+                Err(err) => extraction_failed(err),
             }
         });
 
-        BoxedService { service }
+        BoxedService { service, check, dependencies }
+    }
+
+    pub fn handle(&self, payload: Payload<'_>) -> ServiceResponse {
+        buffered(self.handle_body(payload))
     }
 
-    pub fn handle(&self, payload: Payload) -> impl Into<HttpStatus> {
+    /// Like `handle`, but leaves a streamed body unread.
+    pub fn handle_body(&self, payload: Payload<'_>) -> (HttpStatus, Body) {
         (self.service)(payload)
     }
+
+    /// Extracts the handler's arguments from `payload` without calling it.
+    pub fn check(&self, payload: Payload<'_>) -> Result<(), PayloadError> {
+        (self.check)(payload)
+    }
+
+    /// The types the handler's `Inject<T>` arguments take, with their names.
+    pub fn dependencies(&self) -> &[(TypeId, &'static str)] {
+        &self.dependencies
+    }
+}
+
+fn checker<F, Args, Res>(handler: Arc<Handler<F, Args, Res>>) -> Check
+where
+    Args: 'static,
+    Res: 'static,
+    F: Factory<Args, Res> + Send + Sync + 'static,
+{
+    Arc::new(move |mut payload: Payload<'_>| handler.check(&mut payload))
+}
+
+// A payload the handler can't take is the client's fault, state or a
+// dependency the server wasn't given is not.
+fn extraction_failed(err: PayloadError) -> (HttpStatus, Body) {
+    let status = match err {
+        PayloadError::MissingState | PayloadError::MissingDependency(_) => {
+            println!("Server is misconfigured: {err}");
+            HttpStatus::InternalServerError
+        }
+        _ => {
+            println!("Failed to extract args from payload: {err}");
+            HttpStatus::BadRequest
+        }
+    };
+    (status, Body::Bytes(vec![]))
+}
+
+fn into_response(res: impl Responder) -> (HttpStatus, Body) {
+    match res.respond_body() {
+        Ok(response) => response,
+        Err(msg) => {
+            println!("Handler failed: {msg}");
+            (HttpStatus::InternalServerError, Body::Bytes(vec![]))
+        }
+    }
+}
+
+// Reads a streamed body into memory. A body that fails to read is logged and
+// answered `InternalServerError`, as a failed handler would be.
+pub(crate) fn buffered((status, body): (HttpStatus, Body)) -> ServiceResponse {
+    match body.into_bytes() {
+        Ok(body) => (status, body),
+        Err(err) => {
+            println!("Handler failed: {err}");
+            (HttpStatus::InternalServerError, vec![])
+        }
+    }
 }
 
-pub trait FromPayload: Sized {
-    fn from(payload: &mut Payload) -> Result<Self, String>;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayloadError {
+    UnexpectedEof { needed: usize, remaining: usize },
+    InvalidUtf8,
+    InvalidPercentEncoding,
+    MissingNul,
+    InvalidBool(u8),
+    InvalidFlags(u8),
+    InvalidChar(u32),
+    Zero,
+    NotFinite,
+    InvalidVarint,
+    InvalidTimestamp(u64),
+    OutOfRange { value: i128, lo: i128, hi: i128 },
+    InvalidDiscriminant(u32),
+    MissingRequest,
+    NoRoute,
+    MissingState,
+    MissingDependency(&'static str),
+    TooLarge { len: usize, max: usize },
+    ChecksumMismatch { expected: u32, actual: u32 },
+    TrailingBytes(usize),
 }
 
-pub struct Payload {
-    data: *const u8,
-    len: usize,
+impl Display for PayloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayloadError::UnexpectedEof { needed, remaining } => 
+                write!(f, "unexpected end of payload: needed {needed} bytes, {remaining} remaining"),
+            PayloadError::InvalidUtf8 => write!(f, "invalid utf-8 string"),
+            PayloadError::InvalidPercentEncoding => write!(f, "invalid percent encoding"),
+            PayloadError::MissingNul => write!(f, "string is missing its nul terminator"),
+            PayloadError::InvalidBool(b) => write!(f, "invalid bool {b}"),
+            PayloadError::InvalidFlags(b) => write!(f, "unexpected bits set in flags {b:#010b}"),
+            PayloadError::InvalidChar(c) => write!(f, "invalid char {c:#x}"),
+            PayloadError::Zero => write!(f, "zero value for a non-zero integer"),
+            PayloadError::NotFinite => write!(f, "float is nan or infinite"),
+            PayloadError::InvalidVarint => write!(f, "overlong or overflowing varint"),
+            PayloadError::InvalidTimestamp(secs) => write!(f, "timestamp {secs} is out of range"),
+            PayloadError::OutOfRange { value, lo, hi } => write!(f, "{value} is outside {lo}..={hi}"),
+            PayloadError::InvalidDiscriminant(b) => write!(f, "invalid discriminant {b}"),
+            PayloadError::MissingRequest => write!(f, "payload is not attached to a request"),
+            PayloadError::NoRoute => write!(f, "no route handles the request"),
+            PayloadError::MissingState => write!(f, "no application state of the requested type"),
+            PayloadError::MissingDependency(ty) => write!(f, "no dependency of type {ty} was injected"),
+            PayloadError::TooLarge { len, max } => write!(f, "length {len} exceeds the limit of {max}"),
+            PayloadError::ChecksumMismatch { expected, actual } => write!(f, "checksum {actual:#010x} doesn't match {expected:#010x}"),
+            PayloadError::TrailingBytes(n) => write!(f, "{n} payload bytes left unconsumed"),
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+pub trait FromPayload<'a>: Sized {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+    #[default]
+    Native,
+}
+
+pub trait ToPayload {
+    fn to_payload(&self) -> Vec<u8>;
+}
+
+/// Default limit on the payload length, and on the length prefixes of
+/// variable-length fields read from it.
+pub const DEFAULT_MAX_PAYLOAD_LEN: usize = 16 * 1024 * 1024;
+
+/// Shared values registered with `Server::inject`, keyed by their type.
+pub type Dependencies = HashMap<TypeId, Arc<dyn Any + Send + Sync>>;
+
+/// Told the request path and the number of payload bytes a handler's
+/// arguments left unread, outside strict mode. See `Server::on_unread_payload`.
+pub type UnreadHook = Arc<dyn Fn(&Path, usize) + Send + Sync>;
+
+pub struct Payload<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    order: ByteOrder,
+    max_len: usize,
+    params: Params,
+    query: Query,
+    request: Option<(RequestType, Path)>,
+    id: Option<RequestId>,
+    pattern: Option<Path>,
+    deadline: Option<Instant>,
+    state: Option<Arc<dyn Any + Send + Sync>>,
+    dependencies: Option<Arc<Dependencies>>,
+    on_unread: Option<UnreadHook>,
+    strict: bool,
+    // Set by `Server::validate_request`, which reports nothing.
+    dry: bool,
 }
 
-impl Payload {
+impl<'a> Payload<'a> {
     pub fn len(&self) -> usize {
-        self.len
+        self.remaining()
+    }
+
+    /// Number of bytes not consumed yet.
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Looks at the next `n` bytes without consuming them, or `None` if fewer
+    /// than `n` remain.
+    pub fn peek(&self, n: usize) -> Option<&'a [u8]> {
+        self.bytes.get(self.pos..self.pos.checked_add(n)?)
+    }
+
+    pub fn from_bytes(bytes: &'a [u8]) -> Self {
+        Payload { bytes, pos: 0, order: ByteOrder::default(), max_len: DEFAULT_MAX_PAYLOAD_LEN, params: Params::default(), query: Query::default(), request: None, id: None, pattern: None, deadline: None, state: None, dependencies: None, on_unread: None, strict: false, dry: false }
+    }
+
+    /// Reads a frame of a native-endian `u32` length followed by that many
+    /// bytes into `buf`, replacing its contents, and wraps them. A length over
+    /// `DEFAULT_MAX_PAYLOAD_LEN` fails before the bytes are read.
+    pub fn from_reader(reader: &mut impl Read, buf: &'a mut Vec<u8>) -> io::Result<Self> {
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let len = u32::from_ne_bytes(len) as usize;
+        if len > DEFAULT_MAX_PAYLOAD_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, PayloadError::TooLarge { len, max: DEFAULT_MAX_PAYLOAD_LEN }));
+        }
+
+        buf.clear();
+        reader.take(len as u64).read_to_end(buf)?;
+        if buf.len() < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(Payload::from_bytes(buf))
+    }
+
+    pub fn with_params(mut self, params: Params) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    pub fn with_query(mut self, query: Query) -> Self {
+        self.query = query;
+        self
+    }
+
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    pub fn with_request(mut self, method: RequestType, path: Path) -> Self {
+        self.request = Some((method, path));
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_pattern(mut self, pattern: Path) -> Self {
+        self.pattern = Some(pattern);
+        self
+    }
+
+    pub fn with_request_id(mut self, id: RequestId) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn with_state(mut self, state: Arc<dyn Any + Send + Sync>) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    pub fn with_dependencies(mut self, dependencies: Arc<Dependencies>) -> Self {
+        self.dependencies = Some(dependencies);
+        self
+    }
+
+    pub fn with_unread_hook(mut self, hook: UnreadHook) -> Self {
+        self.on_unread = Some(hook);
+        self
+    }
+
+    pub(crate) fn dry_run(mut self) -> Self {
+        self.dry = true;
+        self
+    }
+
+    /// In strict mode handlers are only called if their arguments consume
+    /// the whole payload.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_byte_order(mut self, order: ByteOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn byte_order(&self) -> ByteOrder {
+        self.order
+    }
+
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    // Called once all handler arguments are extracted. Outside strict mode
+    // unread bytes are only reported, to the unread hook if there is one or
+    // else to the log, and not at all on a dry run.
+    pub(crate) fn finish(&self) -> Result<(), PayloadError> {
+        match self.remaining() {
+            0 => Ok(()),
+            n if self.strict => Err(PayloadError::TrailingBytes(n)),
+            _ if self.dry => Ok(()),
+            n => {
+                match (&self.on_unread, &self.request) {
+                    (Some(hook), Some((_, path))) => hook(path, n),
+                    _ => println!("handler arguments left {n} payload bytes unread"),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// A payload over the next `n` bytes, which this one skips, for parsing a
+    /// length-delimited part on its own. It keeps the byte order and length
+    /// limit of this payload but none of the request it belongs to.
+    pub fn split_to(&mut self, n: usize) -> Result<Payload<'a>, PayloadError> {
+        let bytes = self.take(n)?;
+        Ok(Payload::from_bytes(bytes).with_byte_order(self.order).with_max_len(self.max_len))
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        Payload {
-            data: bytes.as_ptr(),
-            len: bytes.len(),
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8], PayloadError> {
+        if self.len() < n {
+            return Err(PayloadError::UnexpectedEof { needed: n, remaining: self.len() });
         }
+
+        let bytes = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
     }
 }
 
@@ -62,55 +682,942 @@ trait Size: Sized {
 }
 impl<T> Size for T {}
 
-impl<T> FromPayload for T 
-where T: BasicType
+impl<'a, T> FromPayload<'a> for T 
+where T: BasicType
+{
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let order = payload.order;
+        read_basic(payload, order)
+    }
+}
+
+fn read_basic<T: BasicType>(payload: &mut Payload<'_>, order: ByteOrder) -> Result<T, PayloadError> {
+    let bytes = payload.take(T::SIZE)?;
+    Ok(T::from_bytes(bytes, order))
+}
+
+/// A number in big-endian byte order, whatever the order of the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Be<T>(pub T);
+
+/// A number in little-endian byte order, whatever the order of the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Le<T>(pub T);
+
+impl<'a, T> FromPayload<'a> for Be<T>
+where T: BasicType
+{
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        read_basic(payload, ByteOrder::BigEndian).map(Be)
+    }
+}
+
+impl<'a, T> FromPayload<'a> for Le<T>
+where T: BasicType
+{
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        read_basic(payload, ByteOrder::LittleEndian).map(Le)
+    }
+}
+
+impl<T> ToPayload for Be<T>
+where T: BasicType
+{
+    fn to_payload(&self) -> Vec<u8> {
+        self.0.to_bytes(ByteOrder::BigEndian)
+    }
+}
+
+impl<T> ToPayload for Le<T>
+where T: BasicType
+{
+    fn to_payload(&self) -> Vec<u8> {
+        self.0.to_bytes(ByteOrder::LittleEndian)
+    }
+}
+
+impl<T> ToPayload for T 
+where T: BasicType
+{
+    fn to_payload(&self) -> Vec<u8> {
+        self.to_bytes(ByteOrder::Native)
+    }
+}
+
+trait BasicType: Copy {
+    fn from_bytes(bytes: &[u8], order: ByteOrder) -> Self;
+    fn to_bytes(self, order: ByteOrder) -> Vec<u8>;
+}
+macro_rules! mark_basic_type {
+    ($($T: ident),+) => {$(
+        impl BasicType for $T {
+            fn from_bytes(bytes: &[u8], order: ByteOrder) -> Self {
+                let bytes = bytes.try_into().unwrap();
+                match order {
+                    ByteOrder::LittleEndian => $T::from_le_bytes(bytes),
+                    ByteOrder::BigEndian => $T::from_be_bytes(bytes),
+                    ByteOrder::Native => $T::from_ne_bytes(bytes),
+                }
+            }
+
+            fn to_bytes(self, order: ByteOrder) -> Vec<u8> {
+                match order {
+                    ByteOrder::LittleEndian => self.to_le_bytes().into(),
+                    ByteOrder::BigEndian => self.to_be_bytes().into(),
+                    ByteOrder::Native => self.to_ne_bytes().into(),
+                }
+            }
+        }
+    )+};
+}
+
+mark_basic_type!(
+    f32, f64,
+    i8, i16, i32, i64, isize,
+    u8, u16, u32, u64, usize
+);
+
+impl<'a> FromPayload<'a> for bool {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        match <u8 as FromPayload>::from(payload)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(PayloadError::InvalidBool(b)),
+        }
+    }
+}
+
+impl ToPayload for bool {
+    fn to_payload(&self) -> Vec<u8> {
+        vec![*self as u8]
+    }
+}
+
+/// `N` booleans packed into one byte, least significant bit first, so
+/// `self[0]` is bit 0. Bits from `N` up must be clear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Flags<const N: usize>(pub [bool; N]);
+
+impl<const N: usize> Flags<N> {
+    // Evaluated, and failing to compile for `N > 8`, wherever it's used.
+    const FITS_IN_BYTE: () = assert!(N <= 8, "Flags holds at most 8 bits");
+}
+
+impl<const N: usize> Deref for Flags<N> {
+    type Target = [bool; N];
+
+    fn deref(&self) -> &[bool; N] {
+        &self.0
+    }
+}
+
+impl<'a, const N: usize> FromPayload<'a> for Flags<N> {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let () = Self::FITS_IN_BYTE;
+        let byte = <u8 as FromPayload>::from(payload)?;
+        if N < 8 && byte >> N != 0 {
+            return Err(PayloadError::InvalidFlags(byte));
+        }
+        Ok(Flags(std::array::from_fn(|i| byte & (1 << i) != 0)))
+    }
+}
+
+impl<const N: usize> ToPayload for Flags<N> {
+    fn to_payload(&self) -> Vec<u8> {
+        let () = Self::FITS_IN_BYTE;
+        let byte = self.0.iter().enumerate().fold(0u8, |byte, (i, &set)| byte | ((set as u8) << i));
+        vec![byte]
+    }
+}
+
+/// A `char` is sent as its `u32` code point.
+impl<'a> FromPayload<'a> for char {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let c = <u32 as FromPayload>::from(payload)?;
+        char::from_u32(c).ok_or(PayloadError::InvalidChar(c))
+    }
+}
+
+impl ToPayload for char {
+    fn to_payload(&self) -> Vec<u8> {
+        (*self as u32).to_payload()
+    }
+}
+
+macro_rules! impl_from_payload_non_zero {
+    ($($T: ident),+) => {$(
+        impl<'a> FromPayload<'a> for std::num::$T {
+            fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+                std::num::$T::new(FromPayload::from(payload)?).ok_or(PayloadError::Zero)
+            }
+        }
+
+        impl ToPayload for std::num::$T {
+            fn to_payload(&self) -> Vec<u8> {
+                self.get().to_payload()
+            }
+        }
+    )+};
+}
+
+impl_from_payload_non_zero!(
+    NonZeroI8, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroIsize,
+    NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroUsize
+);
+
+impl<'a> FromPayload<'a> for Ipv4Addr {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        <[u8; 4] as FromPayload>::from(payload).map(Into::into)
+    }
+}
+
+impl<'a> FromPayload<'a> for Ipv6Addr {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        <[u8; 16] as FromPayload>::from(payload).map(Into::into)
+    }
+}
+
+impl ToPayload for Ipv4Addr {
+    fn to_payload(&self) -> Vec<u8> {
+        self.octets().into()
+    }
+}
+
+impl ToPayload for Ipv6Addr {
+    fn to_payload(&self) -> Vec<u8> {
+        self.octets().into()
+    }
+}
+
+/// An `IpAddr` is a discriminant byte, `4` or `6`, followed by the octets
+/// of the address in network order.
+impl<'a> FromPayload<'a> for IpAddr {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        match <u8 as FromPayload>::from(payload)? {
+            4 => Ok(IpAddr::V4(FromPayload::from(payload)?)),
+            6 => Ok(IpAddr::V6(FromPayload::from(payload)?)),
+            b => Err(PayloadError::InvalidDiscriminant(b.into())),
+        }
+    }
+}
+
+impl ToPayload for IpAddr {
+    fn to_payload(&self) -> Vec<u8> {
+        match self {
+            IpAddr::V4(ip) => [&[4][..], &ip.octets()].concat(),
+            IpAddr::V6(ip) => [&[6][..], &ip.octets()].concat(),
+        }
+    }
+}
+
+/// A `SocketAddr` is an `IpAddr` followed by a `u16` port.
+impl<'a> FromPayload<'a> for SocketAddr {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let ip = <IpAddr as FromPayload>::from(payload)?;
+        let port = <u16 as FromPayload>::from(payload)?;
+        Ok(SocketAddr::new(ip, port))
+    }
+}
+
+impl ToPayload for SocketAddr {
+    fn to_payload(&self) -> Vec<u8> {
+        let mut bytes = self.ip().to_payload();
+        bytes.extend(self.port().to_payload());
+        bytes
+    }
+}
+
+/// An integer that is rejected unless it lies in `LO..=HI`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ranged<T, const LO: i128, const HI: i128>(pub T);
+
+impl<'a, T, const LO: i128, const HI: i128> FromPayload<'a> for Ranged<T, LO, HI>
+where T: BasicType + TryInto<i128>
+{
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let x = <T as FromPayload>::from(payload)?;
+        match x.try_into() {
+            Ok(value) if (LO..=HI).contains(&value) => Ok(Ranged(x)),
+            Ok(value) => Err(PayloadError::OutOfRange { value, lo: LO, hi: HI }),
+            Err(_) => Err(PayloadError::OutOfRange { value: i128::MAX, lo: LO, hi: HI }),
+        }
+    }
+}
+
+impl<T, const LO: i128, const HI: i128> ToPayload for Ranged<T, LO, HI>
+where T: BasicType
+{
+    fn to_payload(&self) -> Vec<u8> {
+        self.0.to_payload()
+    }
+}
+
+/// An `f32` that is rejected if it is nan or infinite.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct FiniteF32(pub f32);
+
+/// An `f64` that is rejected if it is nan or infinite.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct FiniteF64(pub f64);
+
+macro_rules! impl_from_payload_finite {
+    ($($T: ident($F: ident)),+) => {$(
+        impl<'a> FromPayload<'a> for $T {
+            fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+                let x = <$F as FromPayload>::from(payload)?;
+                x.is_finite().then_some($T(x)).ok_or(PayloadError::NotFinite)
+            }
+        }
+
+        impl ToPayload for $T {
+            fn to_payload(&self) -> Vec<u8> {
+                self.0.to_payload()
+            }
+        }
+    )+};
+}
+
+impl_from_payload_finite!(FiniteF32(f32), FiniteF64(f64));
+
+/// A `Duration` is sent as a `u64` number of milliseconds.
+impl<'a> FromPayload<'a> for Duration {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        <u64 as FromPayload>::from(payload).map(Duration::from_millis)
+    }
+}
+
+/// Sub-millisecond precision is truncated, and durations longer than
+/// `u64::MAX` milliseconds are sent as `u64::MAX`.
+impl ToPayload for Duration {
+    fn to_payload(&self) -> Vec<u8> {
+        u64::try_from(self.as_millis()).unwrap_or(u64::MAX).to_payload()
+    }
+}
+
+/// A point in time sent as a `u64` number of whole seconds since the Unix
+/// epoch. Seconds past what `SystemTime` can hold are rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(pub SystemTime);
+
+impl<'a> FromPayload<'a> for Timestamp {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let secs = <u64 as FromPayload>::from(payload)?;
+        SystemTime::UNIX_EPOCH
+            .checked_add(Duration::from_secs(secs))
+            .map(Timestamp)
+            .ok_or(PayloadError::InvalidTimestamp(secs))
+    }
+}
+
+/// Sub-second precision is truncated, and times before the epoch are sent
+/// as the epoch.
+impl ToPayload for Timestamp {
+    fn to_payload(&self) -> Vec<u8> {
+        let since_epoch = self.0.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        since_epoch.as_secs().to_payload()
+    }
+}
+
+/// An unsigned integer encoded as a LEB128 varint: seven bits per byte, low
+/// bits first, with the high bit set on every byte but the last. Encodings
+/// with more bytes than needed are rejected. Signed types take only values
+/// that fit in a `u64`; encoding a negative one panics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Varint<T>(pub T);
+
+impl<'a, T> FromPayload<'a> for Varint<T>
+where T: TryFrom<u64>
+{
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let bits = std::mem::size_of::<T>() as u32 * 8;
+        let mut value = 0u64;
+        let mut shift = 0;
+
+        loop {
+            let byte = <u8 as FromPayload>::from(payload)?;
+            let low = (byte & 0x7f) as u64;
+            if shift >= bits || (shift > 0 && low >> (bits - shift).min(63) != 0) {
+                return Err(PayloadError::InvalidVarint);
+            }
+            value |= low << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                if byte == 0 && shift > 7 {
+                    return Err(PayloadError::InvalidVarint);
+                }
+                return T::try_from(value).map(Varint).map_err(|_| PayloadError::InvalidVarint);
+            }
+        }
+    }
+}
+
+impl<T> ToPayload for Varint<T>
+where T: Copy, u64: TryFrom<T>
+{
+    fn to_payload(&self) -> Vec<u8> {
+        let Ok(mut value) = u64::try_from(self.0) else {
+            panic!("negative values can't be encoded as a varint");
+        };
+        let mut bytes = vec![];
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+}
+
+/// Width in bytes of the length prefix in front of variable-length fields.
+/// The prefix is a `u32` encoded in the same byte order as the numeric fields.
+pub const LEN_PREFIX_WIDTH: usize = std::mem::size_of::<u32>();
+
+// Reads a length prefix, rejecting it before anything is allocated if it
+// exceeds the payload's limit.
+fn read_len(payload: &mut Payload<'_>) -> Result<usize, PayloadError> {
+    let len = <u32 as FromPayload>::from(payload)? as usize;
+    if len > payload.max_len {
+        return Err(PayloadError::TooLarge { len, max: payload.max_len });
+    }
+
+    Ok(len)
+}
+
+// Writes a length prefix. There's no way to frame a longer field, so a
+// length that doesn't fit in a `u32` panics instead of being truncated.
+pub(crate) fn len_prefix(len: usize) -> Vec<u8> {
+    match u32::try_from(len) {
+        Ok(len) => len.to_payload(),
+        Err(_) => panic!("length {len} doesn't fit in a u32 length prefix"),
+    }
+}
+
+/// Borrows the string out of the payload without copying it.
+impl<'a> FromPayload<'a> for &'a str {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let len = read_len(payload)?;
+        let bytes = payload.take(len)?;
+
+        std::str::from_utf8(bytes).map_err(|_| PayloadError::InvalidUtf8)
+    }
+}
+
+impl ToPayload for &str {
+    fn to_payload(&self) -> Vec<u8> {
+        let mut bytes = len_prefix(self.len());
+        bytes.extend_from_slice(self.as_bytes());
+        bytes
+    }
+}
+
+/// Borrows a nul-terminated string, consuming the terminator too.
+impl<'a> FromPayload<'a> for &'a CStr {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let rest = payload.peek(payload.remaining()).unwrap_or_default();
+        let len = rest.iter().position(|&b| b == 0).ok_or(PayloadError::MissingNul)? + 1;
+        let bytes = payload.take(len)?;
+
+        Ok(CStr::from_bytes_with_nul(bytes).expect("the only nul is the last byte"))
+    }
+}
+
+impl ToPayload for &CStr {
+    fn to_payload(&self) -> Vec<u8> {
+        self.to_bytes_with_nul().into()
+    }
+}
+
+/// Like `&CStr`, but the string must also be valid UTF-8.
+impl<'a> FromPayload<'a> for CString {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let s = <&CStr as FromPayload>::from(payload)?;
+        s.to_str().map_err(|_| PayloadError::InvalidUtf8)?;
+        Ok(s.to_owned())
+    }
+}
+
+impl ToPayload for CString {
+    fn to_payload(&self) -> Vec<u8> {
+        self.as_c_str().to_payload()
+    }
+}
+
+impl<'a> FromPayload<'a> for String {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        <&str as FromPayload>::from(payload).map(str::to_owned)
+    }
+}
+
+impl ToPayload for String {
+    fn to_payload(&self) -> Vec<u8> {
+        self.as_str().to_payload()
+    }
+}
+
+impl<'a, T> FromPayload<'a> for Vec<T>
+where T: FromPayload<'a>
+{
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let count = read_len(payload)?;
+
+        let mut items = Vec::with_capacity(count.min(payload.len()));
+        for _ in 0..count {
+            items.push(T::from(payload)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T> ToPayload for Vec<T>
+where T: ToPayload
+{
+    fn to_payload(&self) -> Vec<u8> {
+        let mut bytes = len_prefix(self.len());
+        for item in self {
+            bytes.extend(item.to_payload());
+        }
+        bytes
+    }
+}
+
+/// A string in a field of exactly `N` bytes, padded at the end with nuls or
+/// spaces, which are trimmed when reading it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedStr<const N: usize>(String);
+
+impl<const N: usize> FixedStr<N> {
+    /// `None` if `s` is longer than `N` bytes.
+    pub fn new(s: impl Into<String>) -> Option<Self> {
+        let s = s.into();
+        (s.len() <= N).then_some(FixedStr(s))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'a, const N: usize> FromPayload<'a> for FixedStr<N> {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let bytes = payload.take(N)?;
+        let s = std::str::from_utf8(bytes).map_err(|_| PayloadError::InvalidUtf8)?;
+        Ok(FixedStr(s.trim_end_matches(['\0', ' ']).to_string()))
+    }
+}
+
+impl<const N: usize> ToPayload for FixedStr<N> {
+    fn to_payload(&self) -> Vec<u8> {
+        let mut bytes = self.0.clone().into_bytes();
+        bytes.resize(N, 0);
+        bytes
+    }
+}
+
+/// A count prefix followed by that many key and value pairs. A repeated key
+/// keeps its last value.
+impl<'a, K, V> FromPayload<'a> for HashMap<K, V>
+where
+    K: FromPayload<'a> + Eq + Hash,
+    V: FromPayload<'a>,
+{
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let count = read_len(payload)?;
+
+        let mut map = HashMap::with_capacity(count.min(payload.len()));
+        for _ in 0..count {
+            let key = K::from(payload)?;
+            map.insert(key, V::from(payload)?);
+        }
+        Ok(map)
+    }
+}
+
+impl<K, V> ToPayload for HashMap<K, V>
+where
+    K: ToPayload,
+    V: ToPayload,
+{
+    fn to_payload(&self) -> Vec<u8> {
+        let mut bytes = len_prefix(self.len());
+        for (key, value) in self {
+            bytes.extend(key.to_payload());
+            bytes.extend(value.to_payload());
+        }
+        bytes
+    }
+}
+
+/// A `Vec<T>` whose count prefix is rejected before anything is allocated if
+/// it exceeds `MAX`, in addition to the payload's own limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoundedVec<T, const MAX: usize>(pub Vec<T>);
+
+impl<T, const MAX: usize> Deref for BoundedVec<T, MAX> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.0
+    }
+}
+
+impl<'a, T, const MAX: usize> FromPayload<'a> for BoundedVec<T, MAX>
+where T: FromPayload<'a>
+{
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let count = read_len(payload)?;
+        if count > MAX {
+            return Err(PayloadError::TooLarge { len: count, max: MAX });
+        }
+
+        let mut items = Vec::with_capacity(count.min(payload.len()));
+        for _ in 0..count {
+            items.push(T::from(payload)?);
+        }
+        Ok(BoundedVec(items))
+    }
+}
+
+impl<T, const MAX: usize> ToPayload for BoundedVec<T, MAX>
+where T: ToPayload
+{
+    fn to_payload(&self) -> Vec<u8> {
+        self.0.to_payload()
+    }
+}
+
+/// Handler argument taking every byte of the payload not consumed by earlier
+/// arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawBody(pub Vec<u8>);
+
+impl<'a> FromPayload<'a> for RawBody {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        <&[u8] as FromPayload>::from(payload).map(|rest| RawBody(rest.to_vec()))
+    }
+}
+
+/// Like `RawBody`, borrowing the bytes instead of copying them.
+impl<'a> FromPayload<'a> for &'a [u8] {
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        payload.take(payload.remaining())
+    }
+}
+
+impl ToPayload for &[u8] {
+    fn to_payload(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl ToPayload for RawBody {
+    fn to_payload(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+}
+
+/// Reads exactly `N` elements with no length prefix.
+impl<'a, T, const N: usize> FromPayload<'a> for [T; N]
+where T: FromPayload<'a>
+{
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let items = (0..N).map(|_| T::from(payload)).collect::<Result<Vec<T>, _>>()?;
+        Ok(items.try_into().unwrap_or_else(|_| unreachable!("collected exactly N items")))
+    }
+}
+
+impl<T, const N: usize> ToPayload for [T; N]
+where T: ToPayload
+{
+    fn to_payload(&self) -> Vec<u8> {
+        self.iter().flat_map(T::to_payload).collect()
+    }
+}
+
+impl<'a, T> FromPayload<'a> for Option<T>
+where T: FromPayload<'a>
+{
+    fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        match <u8 as FromPayload>::from(payload)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::from(payload)?)),
+            b => Err(PayloadError::InvalidDiscriminant(b.into())),
+        }
+    }
+}
+
+impl<T> ToPayload for Option<T>
+where T: ToPayload
+{
+    fn to_payload(&self) -> Vec<u8> {
+        match self {
+            None => vec![0],
+            Some(x) => [&[1][..], &x.to_payload()].concat(),
+        }
+    }
+}
+
+/// Extracts a handler argument from the whole request rather than only the
+/// payload bytes, so it can see the method, path, query, route params and
+/// application state. Every `FromPayload` type is also a `FromRequest`.
+///
+/// `Output` is the value handed to the handler. It is `Self` for owned
+/// extractors and may borrow from the request for extractors like `&str`.
+pub trait FromRequest {
+    type Output<'a>;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self::Output<'a>, PayloadError>;
+
+    /// The type, and its name, that `Server::inject` must be given for the
+    /// extraction to succeed. Checked by `Server::finalize`.
+    fn dependency() -> Option<(TypeId, &'static str)> {
+        None
+    }
+}
+
+impl<T> FromRequest for T
+where T: for<'a> FromPayload<'a>
+{
+    type Output<'a> = T;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<T, PayloadError> {
+        T::from(payload)
+    }
+}
+
+// `&str` handler arguments borrow from the payload of the request being
+// handled, whatever lifetime the handler signature names.
+impl FromRequest for &str {
+    type Output<'a> = &'a str;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<&'a str, PayloadError> {
+        <&str as FromPayload>::from(payload)
+    }
+}
+
+impl FromRequest for &CStr {
+    type Output<'a> = &'a CStr;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<&'a CStr, PayloadError> {
+        <&CStr as FromPayload>::from(payload)
+    }
+}
+
+impl FromRequest for &[u8] {
+    type Output<'a> = &'a [u8];
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<&'a [u8], PayloadError> {
+        <&[u8] as FromPayload>::from(payload)
+    }
+}
+
+impl FromRequest for Params {
+    type Output<'a> = Params;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        Ok(payload.params.clone())
+    }
+}
+
+impl FromRequest for Query {
+    type Output<'a> = Query;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        Ok(payload.query.clone())
+    }
+}
+
+impl FromRequest for RequestType {
+    type Output<'a> = RequestType;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        match &payload.request {
+            Some((method, _)) => Ok(*method),
+            None => Err(PayloadError::MissingRequest),
+        }
+    }
+}
+
+impl FromRequest for Method {
+    type Output<'a> = Method;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        RequestType::from_request(payload).map(Method)
+    }
+}
+
+impl FromRequest for Path {
+    type Output<'a> = Path;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        match &payload.request {
+            Some((_, path)) => Ok(path.clone()),
+            None => Err(PayloadError::MissingRequest),
+        }
+    }
+}
+
+impl FromRequest for FullPath {
+    type Output<'a> = FullPath;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let mut full = Path::from_request(payload)?.as_str().to_string();
+        if !payload.query.as_str().is_empty() {
+            full.push('?');
+            full.push_str(payload.query.as_str());
+        }
+        Ok(FullPath(full))
+    }
+}
+
+impl FromRequest for Deadline {
+    type Output<'a> = Deadline;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        Ok(Deadline(payload.deadline))
+    }
+}
+
+impl FromRequest for MatchedPath {
+    type Output<'a> = MatchedPath;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let pattern = payload.pattern.clone().ok_or(PayloadError::MissingRequest)?;
+        Ok(MatchedPath { pattern, params: payload.params.clone() })
+    }
+}
+
+impl FromRequest for RequestId {
+    type Output<'a> = RequestId;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        payload.id.ok_or(PayloadError::MissingRequest)
+    }
+}
+
+impl FromRequest for Raw {
+    type Output<'a> = Raw;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let Some((ty, path)) = payload.request.clone() else {
+            return Err(PayloadError::MissingRequest);
+        };
+        payload.take(payload.remaining())?;
+
+        Ok(Raw(Request { ty, path, query: payload.query.clone(), payload: payload.bytes.to_vec() }))
+    }
+}
+
+/// Shared application state set with `Server::with_state`. Extracting it
+/// doesn't consume any payload bytes. With no state of type `T` the request
+/// is answered `InternalServerError`.
+pub struct State<T>(Arc<T>);
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> Self {
+        State(self.0.clone())
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for State<T>
+where T: Send + Sync + 'static
+{
+    type Output<'a> = State<T>;
+
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        let state = payload.state.clone().ok_or(PayloadError::MissingState)?;
+        state.downcast::<T>().map(State).map_err(|_| PayloadError::MissingState)
+    }
+}
+
+/// A shared value registered with `Server::inject`. Unlike `State<T>`, any
+/// number of values can be injected, one per type. Extracting it doesn't
+/// consume any payload bytes. If no value of type `T` was injected the
+/// request is answered `InternalServerError`, and `Server::finalize` fails.
+pub struct Inject<T>(pub Arc<T>);
+
+impl<T> Clone for Inject<T> {
+    fn clone(&self) -> Self {
+        Inject(self.0.clone())
+    }
+}
+
+impl<T> Deref for Inject<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for Inject<T>
+where T: Send + Sync + 'static
 {
-    fn from(payload: &mut Payload) -> Result<Self, String> {
-        let payload_size = payload.len();
+    type Output<'a> = Inject<T>;
 
-        if payload_size >= T::SIZE {
-            unsafe {
-                let t_ptr = payload.data as *const T;
-                payload.data = payload.data.add(T::SIZE);
-                Ok(t_ptr.read())
-            }
-        } else {
-            Err("Failed to extract args from payload".into())
-        }
+    fn from_request<'a>(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
+        payload
+            .dependencies
+            .as_ref()
+            .and_then(|deps| deps.get(&TypeId::of::<T>()))
+            .and_then(|dep| dep.clone().downcast::<T>().ok())
+            .map(Inject)
+            .ok_or(PayloadError::MissingDependency(any::type_name::<T>()))
     }
-}
 
-trait BasicType: Copy {}
-macro_rules! mark_basic_type {
-    ($($T: ident),+) => {$(
-        impl BasicType for $T {}
-    )+};
+    fn dependency() -> Option<(TypeId, &'static str)> {
+        Some((TypeId::of::<T>(), any::type_name::<T>()))
+    }
 }
 
-mark_basic_type!(
-    f32, f64,
-    i8, i16, i32, i64, isize,
-    u8, u16, u32, u64, usize
-);
-
-impl FromPayload for () {
-    fn from(_payload: &mut Payload) -> Result<Self, String> {
+impl<'a> FromPayload<'a> for () {
+    fn from(_payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
         Ok(())
     }
 }
 
+impl ToPayload for () {
+    fn to_payload(&self) -> Vec<u8> {
+        vec![]
+    }
+}
+
 macro_rules! tuple_impl_from_payload {(  $( ( $($T: ident,)+ ) ),+ ) => 
     {$(
-        impl<$($T),+> FromPayload for ($($T,)+) 
+        impl<'a, $($T),+> FromPayload<'a> for ($($T,)+) 
         where 
-            $($T: FromPayload),+
+            $($T: FromPayload<'a>),+
         {
             #[allow(non_snake_case)]
-            fn from(payload: &mut Payload) -> Result<Self, String> {
+            fn from(payload: &mut Payload<'a>) -> Result<Self, PayloadError> {
                 $(let $T = $T::from(payload)?;)+
                 Ok(($($T,)+))
             }
         }
+
+        impl<$($T),+> ToPayload for ($($T,)+)
+        where
+            $($T: ToPayload),+
+        {
+            #[allow(non_snake_case)]
+            fn to_payload(&self) -> Vec<u8> {
+                let ($($T,)+) = self;
+                let mut bytes = vec![];
+                $(bytes.extend($T.to_payload());)+
+                bytes
+            }
+        }
     )+};
 }
 
@@ -124,7 +1631,13 @@ tuple_impl_from_payload!(
     (T0, T1, T2, T3, T4, T5, T6, ),
     (T0, T1, T2, T3, T4, T5, T6, T7, ),
     (T0, T1, T2, T3, T4, T5, T6, T7, T8, ),
-    (T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, )
+    (T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, ),
+    (T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, ),
+    (T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, ),
+    (T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, ),
+    (T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, ),
+    (T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, ),
+    (T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, )
 );
 
 #[cfg(test)]
@@ -136,16 +1649,16 @@ mod tests {
     #[test]
     fn test_case_1() -> std::io::Result<()> {
         let mut buf = Vec::<u8>::new();
-        buf.write(&10u8.to_le_bytes())?;
-        buf.write(&10u16.to_le_bytes())?;
-        buf.write(&10u32.to_le_bytes())?;
-        buf.write(&10u64.to_le_bytes())?;
-        buf.write(&10usize.to_le_bytes())?;
-        buf.write(&10i8.to_le_bytes())?;
-        buf.write(&10i16.to_le_bytes())?;
-        buf.write(&10i32.to_le_bytes())?;
-        buf.write(&10i64.to_le_bytes())?;
-        buf.write(&10isize.to_le_bytes())?;
+        buf.write_all(&10u8.to_le_bytes())?;
+        buf.write_all(&10u16.to_le_bytes())?;
+        buf.write_all(&10u32.to_le_bytes())?;
+        buf.write_all(&10u64.to_le_bytes())?;
+        buf.write_all(&10usize.to_le_bytes())?;
+        buf.write_all(&10i8.to_le_bytes())?;
+        buf.write_all(&10i16.to_le_bytes())?;
+        buf.write_all(&10i32.to_le_bytes())?;
+        buf.write_all(&10i64.to_le_bytes())?;
+        buf.write_all(&10isize.to_le_bytes())?;
 
         let mut payload = Payload::from_bytes(&buf);
 
@@ -161,8 +1674,8 @@ mod tests {
     #[test]
     fn test_case_2() -> std::io::Result<()> {
         let mut buf = Vec::<u8>::new();
-        buf.write(&1.5f32.to_le_bytes())?;
-        buf.write(&1.6f64.to_le_bytes())?;
+        buf.write_all(&1.5f32.to_le_bytes())?;
+        buf.write_all(&1.6f64.to_le_bytes())?;
 
         let mut payload = Payload::from_bytes(&buf);
 
@@ -174,4 +1687,496 @@ mod tests {
         );
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn short_payload_for_later_field() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&10u32.to_le_bytes())?;
+
+        let mut payload = Payload::from_bytes(&buf);
+
+        let res = <(u32, u32) as FromPayload>::from(&mut payload);
+        assert_eq!(res, Err(PayloadError::UnexpectedEof { needed: 4, remaining: 0 }));
+        Ok(())
+    }
+
+    #[test]
+    fn misaligned_field() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&7u8.to_le_bytes())?;
+        buf.write_all(&u64::MAX.to_le_bytes())?;
+
+        let mut payload = Payload::from_bytes(&buf);
+
+        let tuple = <(u8, u64) as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(tuple, (7, u64::MAX));
+        Ok(())
+    }
+
+    #[test]
+    fn len_tracks_cursor() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&1u32.to_le_bytes())?;
+        buf.write_all(&2u16.to_le_bytes())?;
+
+        let mut payload = Payload::from_bytes(&buf);
+        assert_eq!(payload.len(), 6);
+
+        <u32 as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(payload.len(), 2);
+
+        <u16 as FromPayload>::from(&mut payload).unwrap();
+        assert!(payload.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn string_field() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&5u32.to_ne_bytes())?;
+        buf.write_all(b"hello")?;
+        buf.write_all(&3u16.to_ne_bytes())?;
+
+        let mut payload = Payload::from_bytes(&buf);
+
+        let tuple = <(String, u16) as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(tuple, ("hello".to_string(), 3));
+        Ok(())
+    }
+
+    #[test]
+    fn borrowed_str_field() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&5u32.to_ne_bytes())?;
+        buf.write_all(b"hello")?;
+
+        let mut payload = Payload::from_bytes(&buf);
+
+        let s = <&str as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(s, "hello");
+        assert_eq!(s.as_ptr(), buf[4..].as_ptr());
+        Ok(())
+    }
+
+    #[test]
+    fn c_string_fields() {
+        let buf = b"ab\0\0c\xff\0d";
+        let mut payload = Payload::from_bytes(buf);
+
+        let (a, empty) = <(&CStr, CString) as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!((a, empty.as_c_str()), (c"ab", c""));
+        assert_eq!(<CString as FromPayload>::from(&mut payload), Err(PayloadError::InvalidUtf8));
+        assert_eq!(<&CStr as FromPayload>::from(&mut payload), Err(PayloadError::MissingNul));
+        assert_eq!(payload.remaining(), 1);
+    }
+
+    #[test]
+    fn fixed_str_field() {
+        let name = FixedStr::<8>::new("rust").unwrap();
+        let buf = [name.to_payload(), b"ab   \0\0\0".to_vec()].concat();
+        assert_eq!(buf.len(), 16);
+
+        let mut payload = Payload::from_bytes(&buf);
+        let (a, b) = <(FixedStr<8>, FixedStr<8>) as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!((a.as_str(), b.as_str()), ("rust", "ab"));
+
+        let mut payload = Payload::from_bytes(&buf[..7]);
+        assert!(<FixedStr<8> as FromPayload>::from(&mut payload).is_err());
+        assert_eq!(FixedStr::<2>::new("abc"), None);
+    }
+
+    #[test]
+    fn invalid_string_field() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&2u32.to_ne_bytes())?;
+        buf.write_all(&[0xff, 0xfe])?;
+        let mut payload = Payload::from_bytes(&buf);
+        assert_eq!(<String as FromPayload>::from(&mut payload), Err(PayloadError::InvalidUtf8));
+
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&10u32.to_ne_bytes())?;
+        buf.write_all(b"short")?;
+        let mut payload = Payload::from_bytes(&buf);
+        assert!(<String as FromPayload>::from(&mut payload).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn vec_field() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&3u32.to_ne_bytes())?;
+        for x in [1i32, -2, 3] {
+            buf.write_all(&x.to_ne_bytes())?;
+        }
+
+        let mut payload = Payload::from_bytes(&buf);
+
+        let xs = <Vec<i32> as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(xs, vec![1, -2, 3]);
+        assert!(payload.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn bounded_vec_field() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&2u32.to_ne_bytes())?;
+        buf.write_all(&5u32.to_ne_bytes())?;
+        buf.write_all(&6u32.to_ne_bytes())?;
+
+        let mut payload = Payload::from_bytes(&buf);
+        assert_eq!(<BoundedVec<u32, 2> as FromPayload>::from(&mut payload), Ok(BoundedVec(vec![5, 6])));
+
+        let mut payload = Payload::from_bytes(&buf);
+        assert_eq!(
+            <BoundedVec<u32, 1> as FromPayload>::from(&mut payload),
+            Err(PayloadError::TooLarge { len: 2, max: 1 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hash_map_field() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&3u32.to_ne_bytes())?;
+        for (k, v) in [(1u32, 0.5f64), (2, 1.5), (1, 2.5)] {
+            buf.write_all(&k.to_ne_bytes())?;
+            buf.write_all(&v.to_ne_bytes())?;
+        }
+
+        let mut payload = Payload::from_bytes(&buf);
+        let map = <HashMap<u32, f64> as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(map, [(1, 2.5), (2, 1.5)].into());
+
+        let mut payload = Payload::from_bytes(&buf).with_max_len(2);
+        assert!(matches!(<HashMap<u32, f64> as FromPayload>::from(&mut payload), Err(PayloadError::TooLarge { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn vec_count_exceeds_payload() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&u32::MAX.to_ne_bytes())?;
+        buf.write_all(&1i32.to_ne_bytes())?;
+
+        let mut payload = Payload::from_bytes(&buf);
+        assert!(<Vec<i32> as FromPayload>::from(&mut payload).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn array_field() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&[7u8; 16])?;
+        for x in [1.0f32, 2.0, 3.0, 4.0] {
+            buf.write_all(&x.to_ne_bytes())?;
+        }
+
+        let mut payload = Payload::from_bytes(&buf);
+        let tuple = <([u8; 16], [f32; 4]) as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(tuple, ([7; 16], [1.0, 2.0, 3.0, 4.0]));
+
+        let mut payload = Payload::from_bytes(&buf[..15]);
+        assert_eq!(
+            <[u8; 16] as FromPayload>::from(&mut payload),
+            Err(PayloadError::UnexpectedEof { needed: 1, remaining: 0 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn peek_does_not_advance() {
+        let buf = [1u8, 2, 3];
+        let mut payload = Payload::from_bytes(&buf);
+
+        assert_eq!(payload.peek(2), Some(&buf[..2]));
+        assert_eq!(payload.peek(4), None);
+        assert_eq!(payload.remaining(), 3);
+
+        <u16 as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(payload.peek(1), Some(&buf[2..]));
+        assert_eq!(payload.peek(0), Some(&[][..]));
+        assert!(!payload.is_empty());
+
+        <u8 as FromPayload>::from(&mut payload).unwrap();
+        assert!(payload.is_empty());
+    }
+
+    #[test]
+    fn raw_body_after_fields() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&3u32.to_ne_bytes())?;
+        buf.write_all(&[1, 2, 3])?;
+
+        let mut payload = Payload::from_bytes(&buf);
+        let len = <u32 as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(payload.remaining(), 3);
+
+        let body = <RawBody as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!((len, body), (3, RawBody(vec![1, 2, 3])));
+        assert_eq!(payload.remaining(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn bool_field() {
+        let buf = [1u8, 0, 2];
+        let mut payload = Payload::from_bytes(&buf);
+
+        let tuple = <(bool, bool) as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(tuple, (true, false));
+        assert_eq!(<bool as FromPayload>::from(&mut payload), Err(PayloadError::InvalidBool(2)));
+    }
+
+    #[test]
+    fn char_field() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&('é' as u32).to_ne_bytes())?;
+        buf.write_all(&0xD800u32.to_ne_bytes())?;
+        buf.write_all(&0x110000u32.to_ne_bytes())?;
+
+        let mut payload = Payload::from_bytes(&buf);
+        assert_eq!(<char as FromPayload>::from(&mut payload), Ok('é'));
+        assert_eq!(<char as FromPayload>::from(&mut payload), Err(PayloadError::InvalidChar(0xD800)));
+        assert_eq!(<char as FromPayload>::from(&mut payload), Err(PayloadError::InvalidChar(0x110000)));
+        Ok(())
+    }
+
+    #[test]
+    fn non_zero_field() -> std::io::Result<()> {
+        use std::num::{NonZeroI16, NonZeroU32};
+
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&7u32.to_ne_bytes())?;
+        buf.write_all(&(-2i16).to_ne_bytes())?;
+        buf.write_all(&0u32.to_ne_bytes())?;
+
+        let mut payload = Payload::from_bytes(&buf);
+        let tuple = <(NonZeroU32, NonZeroI16) as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(tuple, (NonZeroU32::new(7).unwrap(), NonZeroI16::new(-2).unwrap()));
+        assert_eq!(<NonZeroU32 as FromPayload>::from(&mut payload), Err(PayloadError::Zero));
+        Ok(())
+    }
+
+    #[test]
+    fn socket_addr_field() {
+        let v4: SocketAddr = "10.0.0.1:8080".parse().unwrap();
+        let v6: SocketAddr = "[::1]:443".parse().unwrap();
+        let buf = [v4.to_payload(), v6.to_payload()].concat();
+
+        let mut payload = Payload::from_bytes(&buf);
+        let tuple = <(SocketAddr, SocketAddr) as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(tuple, (v4, v6));
+
+        let mut payload = Payload::from_bytes(&[5, 0, 0, 0, 0]);
+        assert_eq!(<IpAddr as FromPayload>::from(&mut payload), Err(PayloadError::InvalidDiscriminant(5)));
+    }
+
+    #[test]
+    fn finite_float_fields() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&1.5f32.to_ne_bytes())?;
+        buf.write_all(&f32::NAN.to_bits().to_ne_bytes())?;
+        buf.write_all(&f64::INFINITY.to_bits().to_ne_bytes())?;
+
+        let mut payload = Payload::from_bytes(&buf);
+        assert_eq!(<FiniteF32 as FromPayload>::from(&mut payload), Ok(FiniteF32(1.5)));
+        assert_eq!(<FiniteF32 as FromPayload>::from(&mut payload), Err(PayloadError::NotFinite));
+        assert_eq!(<FiniteF64 as FromPayload>::from(&mut payload), Err(PayloadError::NotFinite));
+
+        let mut payload = Payload::from_bytes(&buf[4..8]);
+        assert!(<f32 as FromPayload>::from(&mut payload).unwrap().is_nan());
+        Ok(())
+    }
+
+    #[test]
+    fn ranged_field() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&100u32.to_ne_bytes())?;
+        buf.write_all(&0u32.to_ne_bytes())?;
+        buf.write_all(&(-3i8).to_ne_bytes())?;
+
+        let mut payload = Payload::from_bytes(&buf);
+        assert_eq!(<Ranged<u32, 1, 100> as FromPayload>::from(&mut payload), Ok(Ranged(100)));
+        assert_eq!(
+            <Ranged<u32, 1, 100> as FromPayload>::from(&mut payload),
+            Err(PayloadError::OutOfRange { value: 0, lo: 1, hi: 100 })
+        );
+        assert_eq!(<Ranged<i8, -5, 5> as FromPayload>::from(&mut payload), Ok(Ranged(-3)));
+        Ok(())
+    }
+
+    #[test]
+    fn duration_field() {
+        let buf = [Duration::from_millis(1500).to_payload(), 7u8.to_payload()].concat();
+        let mut payload = Payload::from_bytes(&buf);
+
+        let tuple = <(Duration, u8) as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(tuple, (Duration::from_secs_f32(1.5), 7));
+        assert_eq!(Duration::MAX.to_payload(), u64::MAX.to_payload());
+    }
+
+    #[test]
+    fn mixed_endian_fields() {
+        let buf = [0x12, 0x34, 0x56, 0x78, 0x12, 0x34];
+
+        for order in [ByteOrder::BigEndian, ByteOrder::LittleEndian] {
+            let mut payload = Payload::from_bytes(&buf).with_byte_order(order);
+            let tuple = <(Be<u32>, Le<u16>) as FromPayload>::from(&mut payload).unwrap();
+            assert_eq!(tuple, (Be(0x12345678), Le(0x3412)));
+        }
+        assert_eq!([Be(0x12345678u32).to_payload(), Le(0x3412u16).to_payload()].concat(), buf);
+    }
+
+    #[test]
+    fn payload_from_reader() {
+        let frame = [6u32.to_payload(), 7u16.to_payload(), 8u32.to_payload(), vec![9]].concat();
+        let mut reader = &frame[..];
+        let mut buf = vec![];
+
+        let mut payload = Payload::from_reader(&mut reader, &mut buf).unwrap();
+        assert_eq!(<(u16, u32) as FromPayload>::from(&mut payload), Ok((7, 8)));
+        assert!(payload.is_empty());
+        assert_eq!(reader, [9]);
+
+        let err = Payload::from_reader(&mut &frame[..9], &mut buf).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let err = Payload::from_reader(&mut &u32::MAX.to_payload()[..], &mut buf).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn timestamp_fields() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let buf = (Timestamp(time), 5u8).to_payload();
+        assert_eq!(buf, [1_700_000_000u64.to_payload(), vec![5]].concat());
+        assert_eq!(<(Timestamp, u8) as FromPayload>::from(&mut Payload::from_bytes(&buf)), Ok((Timestamp(time), 5)));
+
+        let truncated = Timestamp(time + Duration::from_millis(999)).to_payload();
+        assert_eq!(<Timestamp as FromPayload>::from(&mut Payload::from_bytes(&truncated)), Ok(Timestamp(time)));
+
+        let buf = u64::MAX.to_payload();
+        assert_eq!(<Timestamp as FromPayload>::from(&mut Payload::from_bytes(&buf)), Err(PayloadError::InvalidTimestamp(u64::MAX)));
+    }
+
+    #[test]
+    fn split_payload() {
+        let buf = [4u16.to_payload(), 7u16.to_payload(), 8u16.to_payload(), 9u32.to_payload()].concat();
+        let mut payload = Payload::from_bytes(&buf);
+
+        let len = <u16 as FromPayload>::from(&mut payload).unwrap();
+        let mut part = payload.split_to(len as usize).unwrap();
+        assert_eq!(<(u16, u16) as FromPayload>::from(&mut part), Ok((7, 8)));
+        assert!(part.is_empty());
+        assert_eq!(<u32 as FromPayload>::from(&mut part), Err(PayloadError::UnexpectedEof { needed: 4, remaining: 0 }));
+
+        assert_eq!(<u32 as FromPayload>::from(&mut payload), Ok(9));
+        assert!(matches!(payload.split_to(1), Err(PayloadError::UnexpectedEof { needed: 1, remaining: 0 })));
+    }
+
+    #[test]
+    fn packed_flags() {
+        let decode = |byte: u8| <Flags<3> as FromPayload>::from(&mut Payload::from_bytes(&[byte]));
+        assert_eq!(decode(0b101), Ok(Flags([true, false, true])));
+        assert_eq!(decode(0b1000), Err(PayloadError::InvalidFlags(0b1000)));
+        assert_eq!(Flags([false, true, true]).to_payload(), [0b110]);
+
+        let all = <Flags<8> as FromPayload>::from(&mut Payload::from_bytes(&[0xff]));
+        assert_eq!(all, Ok(Flags([true; 8])));
+    }
+
+    #[test]
+    fn varint_fields() {
+        for value in [0u64, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let buf = Varint(value).to_payload();
+            let mut payload = Payload::from_bytes(&buf);
+            assert_eq!(<Varint<u64> as FromPayload>::from(&mut payload), Ok(Varint(value)));
+            assert!(payload.is_empty());
+        }
+        assert_eq!(Varint(300u16).to_payload(), [0xac, 0x02]);
+
+        let buf = Varint(usize::MAX).to_payload();
+        assert_eq!(<Varint<usize> as FromPayload>::from(&mut Payload::from_bytes(&buf)), Ok(Varint(usize::MAX)));
+        let buf = Varint(i32::MAX).to_payload();
+        assert_eq!(<Varint<i32> as FromPayload>::from(&mut Payload::from_bytes(&buf)), Ok(Varint(i32::MAX)));
+
+        let decode = |buf: &[u8]| <Varint<u16> as FromPayload>::from(&mut Payload::from_bytes(buf));
+        assert_eq!(decode(&[0xff, 0xff, 0x03]), Ok(Varint(u16::MAX)));
+        assert_eq!(decode(&[0xff, 0xff, 0x04]), Err(PayloadError::InvalidVarint));
+        assert_eq!(decode(&[0x80, 0x80, 0x80, 0x01]), Err(PayloadError::InvalidVarint));
+        assert_eq!(decode(&[0x81, 0x00]), Err(PayloadError::InvalidVarint));
+        assert!(matches!(decode(&[0x81]), Err(PayloadError::UnexpectedEof { .. })));
+    }
+
+    #[test]
+    fn option_field() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&[1])?;
+        buf.write_all(&4u32.to_ne_bytes())?;
+        buf.write_all(b"rust")?;
+        buf.write_all(&[0])?;
+        buf.write_all(&9u8.to_ne_bytes())?;
+
+        let mut payload = Payload::from_bytes(&buf);
+
+        let tuple = <(Option<String>, Option<u32>) as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(tuple, (Some("rust".to_string()), None));
+        assert_eq!(payload.len(), 1);
+
+        let buf = [3u8];
+        let mut payload = Payload::from_bytes(&buf);
+        assert_eq!(<Option<u8> as FromPayload>::from(&mut payload), Err(PayloadError::InvalidDiscriminant(3)));
+        Ok(())
+    }
+
+    #[test]
+    fn big_endian_fields() -> std::io::Result<()> {
+        let mut buf = Vec::<u8>::new();
+        buf.write_all(&0x0102u16.to_be_bytes())?;
+        buf.write_all(&2u32.to_be_bytes())?;
+        buf.write_all(b"be")?;
+        buf.write_all(&1.5f64.to_be_bytes())?;
+
+        let mut payload = Payload::from_bytes(&buf).with_byte_order(ByteOrder::BigEndian);
+
+        let tuple = <(u16, String, f64) as FromPayload>::from(&mut payload).unwrap();
+        assert_eq!(tuple, (0x0102, "be".to_string(), 1.5));
+        Ok(())
+    }
+
+    #[test]
+    fn to_payload_round_trip() {
+        let names = vec!["a".to_string(), "bc".to_string()];
+        let bytes = names.to_payload();
+
+        let mut payload = Payload::from_bytes(&bytes);
+        assert_eq!(<Vec<String> as FromPayload>::from(&mut payload).unwrap(), names);
+        assert!(payload.is_empty());
+
+        let bytes = 1.5f32.to_payload();
+        let mut payload = Payload::from_bytes(&bytes);
+        assert_eq!(<f32 as FromPayload>::from(&mut payload).unwrap(), 1.5);
+    }
+
+    #[test]
+    fn status_codes() {
+        assert_eq!(HttpStatus::Success, HttpStatus::Ok);
+        assert_eq!(HttpStatus::Ok.code(), 200);
+        assert_eq!(HttpStatus::NotFound.code(), 404);
+        assert_eq!(HttpStatus::Failed.code(), 500);
+    }
+
+    #[test]
+    fn oversized_length_prefix() {
+        let buf = u32::MAX.to_payload();
+
+        let mut payload = Payload::from_bytes(&buf).with_max_len(1024);
+        assert_eq!(
+            <Vec<u64> as FromPayload>::from(&mut payload),
+            Err(PayloadError::TooLarge { len: u32::MAX as usize, max: 1024 })
+        );
+
+        let mut payload = Payload::from_bytes(&buf).with_max_len(1024);
+        assert!(matches!(<String as FromPayload>::from(&mut payload), Err(PayloadError::TooLarge { .. })));
+    }
+}
@@ -0,0 +1,64 @@
+use crate::request::{Path, Request, RequestBuilder, RequestType};
+use crate::server::Server;
+use crate::service::{ServiceResponse, ToPayload};
+
+/// Drives a `Server` in memory, without a socket, the way a client would:
+///
+///   let (status, body) = TestClient::new(&server).get("/book").send_fields((10u32,));
+pub struct TestClient<'a> {
+    server: &'a Server,
+}
+
+impl<'a> TestClient<'a> {
+    pub fn new(server: &'a Server) -> Self {
+        TestClient { server }
+    }
+
+    pub fn request(&self, method: RequestType, path: impl Into<Path>) -> TestRequest<'a> {
+        TestRequest { server: self.server, builder: Request::builder().method(method).path(path) }
+    }
+
+    pub fn get(&self, path: impl Into<Path>) -> TestRequest<'a> {
+        self.request(RequestType::Get, path)
+    }
+
+    pub fn post(&self, path: impl Into<Path>) -> TestRequest<'a> {
+        self.request(RequestType::Post, path)
+    }
+
+    pub fn put(&self, path: impl Into<Path>) -> TestRequest<'a> {
+        self.request(RequestType::Put, path)
+    }
+
+    pub fn delete(&self, path: impl Into<Path>) -> TestRequest<'a> {
+        self.request(RequestType::Delete, path)
+    }
+
+    pub fn patch(&self, path: impl Into<Path>) -> TestRequest<'a> {
+        self.request(RequestType::Patch, path)
+    }
+}
+
+/// A request being built by a `TestClient`.
+pub struct TestRequest<'a> {
+    server: &'a Server,
+    builder: RequestBuilder,
+}
+
+impl TestRequest<'_> {
+    pub fn field(mut self, field: impl ToPayload) -> Self {
+        self.builder = self.builder.field(field);
+        self
+    }
+
+    pub fn send(self) -> ServiceResponse {
+        let request = self.builder.build().expect("test requests have a method and a path");
+        self.server.handle_request(request)
+    }
+
+    /// Sends `fields`, usually a tuple of the handler's arguments, as the
+    /// payload.
+    pub fn send_fields(self, fields: impl ToPayload) -> ServiceResponse {
+        self.field(fields).send()
+    }
+}